@@ -0,0 +1,595 @@
+//! Async/await support.
+//!
+//! This module provides `async` versions of the transmit and receive operations, which wait for
+//! the corresponding bxCAN interrupt instead of returning [`WouldBlock`][nb::Error::WouldBlock].
+//!
+//! To use them, the CAN instance has to implement [`AsyncInstance`], and the bxCAN interrupt
+//! handlers have to call the hooks in this module:
+//!
+//! | Interrupt | Hook |
+//! |-----------|------|
+//! | TX        | [`on_tx_interrupt`] |
+//! | RX FIFO 0 | [`on_rx0_interrupt`] |
+//! | RX FIFO 1 | [`on_rx1_interrupt`] |
+//! | SCE       | [`on_sce_interrupt`] |
+//!
+//! The futures enable the interrupts they wait for, and the hooks disable or acknowledge them
+//! again. Since interrupt enable bits are modified concurrently, the methods of [`Can`] that
+//! enable or disable interrupts must not be used to control the [`Interrupt`]s used here.
+//!
+//! Accessing the interrupt enable register requires a critical section, which is provided by the
+//! [`critical-section`] crate. The application has to select an implementation for it.
+//!
+//! [`Interrupt`]: crate::Interrupt
+//! [`critical-section`]: https://docs.rs/critical-section
+
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use critical_section::Mutex;
+
+use crate::pac::can::RegisterBlock;
+use crate::{
+    Can, ErrorStatus, Frame, Instance, Interrupts, OverrunError, Rx0, Rx1, TransmitStatus, Tx,
+};
+
+/// A bxCAN peripheral instance that can be used with the `async` API.
+///
+/// HALs implement this trait alongside [`Instance`], by providing a `static` [`Wakers`] instance
+/// for each CAN peripheral:
+///
+/// ```
+/// use bxcan::asynch::{AsyncInstance, Wakers};
+/// # struct CAN1;
+/// # unsafe impl bxcan::Instance for CAN1 {
+/// #     const REGISTERS: *mut bxcan::RegisterBlock = 0x4000_6400 as *mut _;
+/// # }
+///
+/// impl AsyncInstance for CAN1 {
+///     fn wakers() -> &'static Wakers {
+///         static WAKERS: Wakers = Wakers::new();
+///         &WAKERS
+///     }
+/// }
+/// ```
+pub trait AsyncInstance: Instance {
+    /// Returns the wakers associated with this instance.
+    ///
+    /// This must return the same [`Wakers`] on every call.
+    fn wakers() -> &'static Wakers;
+}
+
+/// Storage for the wakers of tasks waiting on a CAN peripheral.
+pub struct Wakers {
+    tx: WakerSlot,
+    rx0: WakerSlot,
+    rx1: WakerSlot,
+    sce: WakerSlot,
+    error_pending: Mutex<Cell<bool>>,
+    /// The FIFO 0 and FIFO 1 interrupts disabled by the RX hooks, to be restored when waiting again.
+    rx_masked: Mutex<Cell<Interrupts>>,
+}
+
+impl Wakers {
+    /// Creates an empty set of wakers.
+    pub const fn new() -> Self {
+        Self {
+            tx: WakerSlot::new(),
+            rx0: WakerSlot::new(),
+            rx1: WakerSlot::new(),
+            sce: WakerSlot::new(),
+            error_pending: Mutex::new(Cell::new(false)),
+            rx_masked: Mutex::new(Cell::new(Interrupts::empty())),
+        }
+    }
+}
+
+impl Default for Wakers {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct WakerSlot(Mutex<RefCell<Option<Waker>>>);
+
+impl WakerSlot {
+    const fn new() -> Self {
+        Self(Mutex::new(RefCell::new(None)))
+    }
+
+    fn register(&self, waker: &Waker) {
+        critical_section::with(|cs| {
+            let mut slot = self.0.borrow_ref_mut(cs);
+            match &*slot {
+                Some(registered) if registered.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        })
+    }
+
+    fn wake(&self) {
+        let waker = critical_section::with(|cs| self.0.borrow_ref_mut(cs).take());
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+fn enable_interrupts(can: &RegisterBlock, interrupts: Interrupts) {
+    critical_section::with(|_| {
+        can.ier
+            .modify(|r, w| unsafe { w.bits(r.bits() | interrupts.bits()) })
+    })
+}
+
+fn disable_interrupts(can: &RegisterBlock, interrupts: Interrupts) {
+    critical_section::with(|_| {
+        can.ier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !interrupts.bits()) })
+    })
+}
+
+/// Disables those of `interrupts` that are enabled, and remembers them in `masked`.
+fn mask_interrupts(can: &RegisterBlock, interrupts: Interrupts, masked: &Mutex<Cell<Interrupts>>) {
+    critical_section::with(|cs| {
+        let enabled = Interrupts::from_bits_truncate(can.ier.read().bits()) & interrupts;
+        can.ier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !enabled.bits()) });
+        let masked = masked.borrow(cs);
+        masked.set(masked.get() | enabled);
+    })
+}
+
+/// Enables `interrupts`, and restores those of `group` that were disabled by
+/// [`mask_interrupts`].
+fn unmask_interrupts(
+    can: &RegisterBlock,
+    interrupts: Interrupts,
+    group: Interrupts,
+    masked: &Mutex<Cell<Interrupts>>,
+) {
+    critical_section::with(|cs| {
+        let masked = masked.borrow(cs);
+        let restored = masked.get() & group;
+        masked.set(masked.get() - group);
+        can.ier
+            .modify(|r, w| unsafe { w.bits(r.bits() | interrupts.bits() | restored.bits()) });
+    })
+}
+
+const FIFO0_INTERRUPTS: Interrupts = Interrupts::from_bits_truncate(
+    Interrupts::FIFO0_MESSAGE_PENDING.bits()
+        | Interrupts::FIFO0_FULL.bits()
+        | Interrupts::FIFO0_OVERRUN.bits(),
+);
+
+const FIFO1_INTERRUPTS: Interrupts = Interrupts::from_bits_truncate(
+    Interrupts::FIFO1_MESSAGE_PENDING.bits()
+        | Interrupts::FIFO1_FULL.bits()
+        | Interrupts::FIFO1_OVERRUN.bits(),
+);
+
+/// Handles the **TX** interrupt of a CAN peripheral.
+///
+/// Acknowledging the interrupt would discard the outcomes of the finished requests, so this
+/// disables the TX interrupt until the next call to [`Tx::transmit_async`], and wakes the task
+/// waiting in it. The outcomes can still be read with [`Tx::poll_completion`] and
+/// [`Tx::take_completions`].
+pub fn on_tx_interrupt<I: AsyncInstance>() {
+    let can = unsafe { &*I::REGISTERS };
+    disable_interrupts(can, Interrupts::TRANSMIT_MAILBOX_EMPTY);
+    I::wakers().tx.wake();
+}
+
+/// Handles the **RX FIFO 0** interrupt of a CAN peripheral.
+///
+/// The interrupt condition can only be cleared by receiving frames, so this disables the enabled
+/// FIFO 0 interrupts until the next call to [`Rx0::receive_async`], which enables them again, and
+/// wakes the task waiting in it.
+pub fn on_rx0_interrupt<I: AsyncInstance>() {
+    let can = unsafe { &*I::REGISTERS };
+    let wakers = I::wakers();
+    mask_interrupts(can, FIFO0_INTERRUPTS, &wakers.rx_masked);
+    wakers.rx0.wake();
+}
+
+/// Handles the **RX FIFO 1** interrupt of a CAN peripheral.
+///
+/// The interrupt condition can only be cleared by receiving frames, so this disables the enabled
+/// FIFO 1 interrupts until the next call to [`Rx1::receive_async`], which enables them again, and
+/// wakes the task waiting in it.
+pub fn on_rx1_interrupt<I: AsyncInstance>() {
+    let can = unsafe { &*I::REGISTERS };
+    let wakers = I::wakers();
+    mask_interrupts(can, FIFO1_INTERRUPTS, &wakers.rx_masked);
+    wakers.rx1.wake();
+}
+
+/// Handles the **SCE** interrupt of a CAN peripheral.
+///
+/// This acknowledges the error, wakeup and sleep interrupts (see [`Can::clear_error_interrupt`],
+/// [`Can::clear_wakeup_interrupt`] and [`Can::clear_sleep_interrupt`]), and wakes the task
+/// waiting in [`Can::wait_for_error`].
+pub fn on_sce_interrupt<I: AsyncInstance>() {
+    let can = unsafe { &*I::REGISTERS };
+    let wakers = I::wakers();
+    if can.msr.read().erri().bit_is_set() {
+        critical_section::with(|cs| wakers.error_pending.borrow(cs).set(true));
+    }
+    crate::clear_error_flag(can);
+    crate::clear_wakeup_flag(can);
+    crate::clear_sleep_flag(can);
+    wakers.sce.wake();
+}
+
+impl<I: AsyncInstance> Tx<I> {
+    /// Puts a CAN frame in a transmit mailbox for transmission on the bus, waiting for a mailbox to
+    /// become available if necessary.
+    ///
+    /// This behaves like [`Tx::transmit`], but waits for the **TX** interrupt instead of returning
+    /// [`WouldBlock`][nb::Error::WouldBlock]. It requires [`on_tx_interrupt`] to be called from
+    /// the TX interrupt handler.
+    pub async fn transmit_async(&mut self, frame: &Frame) -> TransmitStatus {
+        poll_fn(|cx| {
+            // Register first, so that an interrupt firing in between can not be missed.
+            I::wakers().tx.register(cx.waker());
+            match self.transmit(frame) {
+                Ok(status) => Poll::Ready(status),
+                Err(nb::Error::WouldBlock) => {
+                    // Acknowledge requests that finished before waiting for the next one, without
+                    // losing their outcomes.
+                    self.stash_completions();
+                    enable_interrupts(self.registers(), Interrupts::TRANSMIT_MAILBOX_EMPTY);
+                    Poll::Pending
+                }
+                Err(nb::Error::Other(void)) => match void {},
+            }
+        })
+        .await
+    }
+}
+
+impl<I: AsyncInstance> Rx0<I> {
+    /// Waits for a frame to arrive in FIFO 0 and returns it.
+    ///
+    /// Returns `Err` when a frame was lost due to buffer overrun.
+    ///
+    /// This requires [`on_rx0_interrupt`] to be called from the RX FIFO 0 interrupt handler.
+    pub async fn receive_async(&mut self) -> Result<Frame, OverrunError> {
+        poll_fn(|cx| {
+            I::wakers().rx0.register(cx.waker());
+            match self.receive() {
+                Ok(frame) => Poll::Ready(Ok(frame)),
+                Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                Err(nb::Error::WouldBlock) => {
+                    unmask_interrupts(
+                        self.registers(),
+                        Interrupts::FIFO0_MESSAGE_PENDING,
+                        FIFO0_INTERRUPTS,
+                        &I::wakers().rx_masked,
+                    );
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl<I: AsyncInstance> Rx1<I> {
+    /// Waits for a frame to arrive in FIFO 1 and returns it.
+    ///
+    /// Returns `Err` when a frame was lost due to buffer overrun.
+    ///
+    /// This requires [`on_rx1_interrupt`] to be called from the RX FIFO 1 interrupt handler.
+    pub async fn receive_async(&mut self) -> Result<Frame, OverrunError> {
+        poll_fn(|cx| {
+            I::wakers().rx1.register(cx.waker());
+            match self.receive() {
+                Ok(frame) => Poll::Ready(Ok(frame)),
+                Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                Err(nb::Error::WouldBlock) => {
+                    unmask_interrupts(
+                        self.registers(),
+                        Interrupts::FIFO1_MESSAGE_PENDING,
+                        FIFO1_INTERRUPTS,
+                        &I::wakers().rx_masked,
+                    );
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl<I: AsyncInstance> Can<I> {
+    /// Waits for an error interrupt and returns the error status at that point.
+    ///
+    /// The error conditions to wait for are selected by enabling any of
+    /// [`Interrupt::ErrorWarning`], [`Interrupt::ErrorPassive`], [`Interrupt::BusOff`] and
+    /// [`Interrupt::LastErrorCode`] beforehand. [`Interrupt::Error`] is enabled by this method.
+    ///
+    /// This requires [`on_sce_interrupt`] to be called from the SCE interrupt handler.
+    ///
+    /// [`Interrupt::ErrorWarning`]: crate::Interrupt::ErrorWarning
+    /// [`Interrupt::ErrorPassive`]: crate::Interrupt::ErrorPassive
+    /// [`Interrupt::BusOff`]: crate::Interrupt::BusOff
+    /// [`Interrupt::LastErrorCode`]: crate::Interrupt::LastErrorCode
+    /// [`Interrupt::Error`]: crate::Interrupt::Error
+    pub async fn wait_for_error(&mut self) -> ErrorStatus {
+        poll_fn(|cx| {
+            let wakers = I::wakers();
+            wakers.sce.register(cx.waker());
+            if critical_section::with(|cs| wakers.error_pending.borrow(cs).replace(false)) {
+                Poll::Ready(self.error_status())
+            } else {
+                enable_interrupts(self.registers(), Interrupts::ERROR);
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::future::Future;
+    use core::pin::pin;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use core::task::Context;
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::sim::{Fault, Sim, SimCan3};
+    use crate::{Mailbox, StandardId, TransmitResult};
+
+    /// A global lock standing in for disabling interrupts.
+    ///
+    /// The `std` implementation of `critical-section` can not be used, since its restore state
+    /// conflicts with the one selected by `cortex-m` when building the whole workspace.
+    struct TestCriticalSection;
+    critical_section::set_impl!(TestCriticalSection);
+
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    unsafe impl critical_section::Impl for TestCriticalSection {
+        unsafe fn acquire() -> critical_section::RawRestoreState {
+            while LOCKED
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+            Default::default()
+        }
+
+        unsafe fn release(_: critical_section::RawRestoreState) {
+            LOCKED.store(false, Ordering::Release);
+        }
+    }
+
+    /// Defines an instance whose registers are plain RAM.
+    macro_rules! ram_instance {
+        ($name:ident) => {
+            struct $name;
+
+            unsafe impl Instance for $name {
+                const REGISTERS: *mut RegisterBlock = {
+                    const WORDS: usize = core::mem::size_of::<RegisterBlock>() / 4;
+                    static mut MEM: [u32; WORDS] = [0; WORDS];
+                    core::ptr::addr_of_mut!(MEM) as *mut RegisterBlock
+                };
+            }
+
+            impl AsyncInstance for $name {
+                fn wakers() -> &'static Wakers {
+                    static WAKERS: Wakers = Wakers::new();
+                    &WAKERS
+                }
+            }
+        };
+    }
+
+    impl AsyncInstance for SimCan3 {
+        fn wakers() -> &'static Wakers {
+            static WAKERS: Wakers = Wakers::new();
+            &WAKERS
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl CountingWaker {
+        fn count(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    fn regs<I: Instance>() -> &'static RegisterBlock {
+        unsafe { &*I::REGISTERS }
+    }
+
+    /// Sets the value of a register that is read-only for software.
+    fn poke<T>(reg: &T, value: u32) {
+        unsafe { (reg as *const T as *mut u32).write_volatile(value) }
+    }
+
+    fn ier<I: Instance>() -> Interrupts {
+        Interrupts::from_bits_truncate(regs::<I>().ier.read().bits())
+    }
+
+    #[test]
+    fn receive_wakes_on_rx_interrupt() {
+        ram_instance!(Can0);
+        let can = regs::<Can0>();
+        let mut rx = unsafe { Rx1::<Can0>::conjure() };
+        can.ier
+            .write(|w| unsafe { w.bits(Interrupts::FIFO1_FULL.bits()) });
+
+        let state = Arc::new(CountingWaker::default());
+        let waker = Waker::from(state.clone());
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut fut = pin!(rx.receive_async());
+
+            // FIFO is empty.
+            assert!(fut.as_mut().poll(&mut cx).is_pending());
+            assert!(ier::<Can0>().contains(Interrupts::FIFO1_MESSAGE_PENDING));
+            assert_eq!(state.count(), 0);
+
+            // A frame arrives.
+            poke(&can.rx[1].rir, 5 << 21);
+            poke(&can.rx[1].rdtr, 2);
+            poke(&can.rx[1].rdlr, 0x0000_0201);
+            can.rfr[1].write(|w| unsafe { w.bits(1) });
+            on_rx1_interrupt::<Can0>();
+            assert!(!ier::<Can0>()
+                .intersects(Interrupts::FIFO1_MESSAGE_PENDING | Interrupts::FIFO1_FULL));
+            assert_eq!(state.count(), 1);
+
+            let expected = Frame::new_data(StandardId::new(5).unwrap(), [1, 2]);
+            assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(Ok(expected)));
+        }
+
+        // Waiting for the next frame enables the interrupts again, including the FIFO full
+        // interrupt enabled by the application.
+        let mut fut = pin!(rx.receive_async());
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(
+            ier::<Can0>(),
+            Interrupts::FIFO1_MESSAGE_PENDING | Interrupts::FIFO1_FULL
+        );
+    }
+
+    #[test]
+    fn transmit_waits_for_free_mailbox() {
+        ram_instance!(Can0);
+        let can = regs::<Can0>();
+        let mut tx = unsafe { Tx::<Can0>::conjure() };
+
+        // All mailboxes are pending, and FIFO order prevents dequeuing.
+        can.mcr.write(|w| w.txfp().set_bit());
+        can.tsr.write(|w| unsafe { w.bits(0) });
+
+        let state = Arc::new(CountingWaker::default());
+        let waker = Waker::from(state.clone());
+        let mut cx = Context::from_waker(&waker);
+        let frame = Frame::new_data(StandardId::new(1).unwrap(), [1, 2, 3]);
+        let mut fut = pin!(tx.transmit_async(&frame));
+
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert!(ier::<Can0>().contains(Interrupts::TRANSMIT_MAILBOX_EMPTY));
+
+        // Mailbox 2 finishes transmission.
+        on_tx_interrupt::<Can0>();
+        assert_eq!(state.count(), 1);
+        can.tsr.write(|w| unsafe { w.bits(1 << 28 | 2 << 24) });
+
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(status) => {
+                assert_eq!(status.mailbox(), Mailbox::Mailbox2);
+                assert!(status.dequeued_frame().is_none());
+            }
+            Poll::Pending => panic!("transmission should have been enqueued"),
+        }
+        assert_eq!(can.tx[2].tir.read().bits(), 1 << 21 | 1);
+    }
+
+    #[test]
+    fn completions_survive_tx_interrupt() {
+        let (mut sim, p) = Sim::new();
+        let mut can = Can::builder(p.can3)
+            .set_loopback(true)
+            .set_silent(true)
+            .enable();
+        let tx = can.split_by_ref().0;
+
+        let state = Arc::new(CountingWaker::default());
+        let waker = Waker::from(state.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // Fill all mailboxes with frames of increasing priority.
+        let tokens = [3, 2, 1].map(|id| {
+            let frame = Frame::new_data(StandardId::new(id).unwrap(), []);
+            tx.transmit(&frame).unwrap().token()
+        });
+
+        // A frame of lower priority has to wait for all of them.
+        {
+            let frame = Frame::new_data(StandardId::new(4).unwrap(), []);
+            let mut fut = pin!(tx.transmit_async(&frame));
+            assert!(fut.as_mut().poll(&mut cx).is_pending());
+
+            let mut done = false;
+            for _ in 0..3 {
+                sim.step();
+                assert!(ier::<SimCan3>().contains(Interrupts::TRANSMIT_MAILBOX_EMPTY));
+                on_tx_interrupt::<SimCan3>();
+                assert!(!ier::<SimCan3>().contains(Interrupts::TRANSMIT_MAILBOX_EMPTY));
+                if let Poll::Ready(status) = fut.as_mut().poll(&mut cx) {
+                    assert_eq!(status.dequeued_token(), None);
+                    done = true;
+                    break;
+                }
+            }
+            assert!(done);
+        }
+
+        // The outcomes of the three frames were not lost by acknowledging the interrupt, and are
+        // reported in the order the frames were sent.
+        let completions = tx.take_completions().collect::<Vec<_>>();
+        assert_eq!(completions.len(), 3);
+        for (completion, token) in completions.iter().zip(tokens.iter().rev()) {
+            assert_eq!(completion.result(), TransmitResult::Transmitted);
+            assert_eq!(completion.token(), Some(*token));
+        }
+    }
+
+    #[test]
+    fn wait_for_error_wakes_on_sce_interrupt() {
+        let (mut sim, p) = Sim::new();
+        let mut can = Can::builder(p.can3).enable();
+        can.enable_interrupts(Interrupts::BUS_OFF);
+        let frame = Frame::new_data(StandardId::new(0x10).unwrap(), []);
+        can.transmit(&frame).unwrap();
+
+        let state = Arc::new(CountingWaker::default());
+        let waker = Waker::from(state.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(can.wait_for_error());
+
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert!(ier::<SimCan3>().contains(Interrupts::ERROR));
+
+        // Errors below bus-off do not complete the future.
+        for step in 1..=32 {
+            sim.inject_fault(Fault::BitRecessive);
+            assert!(!sim.step());
+            on_sce_interrupt::<SimCan3>();
+            assert_eq!(state.count(), step);
+            assert!(regs::<SimCan3>().msr.read().erri().bit_is_clear());
+            if step < 32 {
+                assert!(fut.as_mut().poll(&mut cx).is_pending());
+            }
+        }
+
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(status) => assert!(status.bus_off()),
+            Poll::Pending => panic!("error should have been reported"),
+        }
+    }
+}
@@ -0,0 +1,517 @@
+//! Bit timing calculation.
+
+/// Maximum value of the baud rate prescaler (BRP + 1).
+const MAX_PRESCALER: u32 = 1024;
+/// Maximum length of time segment 1 in time quanta (TS1 + 1).
+const MAX_SEG1: u32 = 16;
+/// Maximum length of time segment 2 in time quanta (TS2 + 1).
+const MAX_SEG2: u32 = 8;
+/// Maximum resynchronization jump width in time quanta (SJW + 1).
+const MAX_SJW: u8 = 4;
+/// Largest bitrate error accepted by [`BitTiming::calculate`], in units of 1/10000.
+///
+/// This is the maximum oscillator tolerance allowed by the CAN specification.
+const MAX_BITRATE_ERROR: u64 = 158;
+
+/// Bit timing parameters of a bxCAN peripheral.
+///
+/// A bit is divided into *time quanta* (tq), whose length is determined by the prescaler applied
+/// to the peripheral clock. Each bit consists of a 1 tq synchronization segment, followed by
+/// *time segment 1* and *time segment 2*. The bit is sampled between the two time segments.
+///
+/// The [`BitTiming::btr`] value can be passed to [`CanBuilder::set_bit_timing`] and
+/// [`CanConfig::set_bit_timing`].
+///
+/// [`CanBuilder::set_bit_timing`]: crate::CanBuilder::set_bit_timing
+/// [`CanConfig::set_bit_timing`]: crate::CanConfig::set_bit_timing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct BitTiming {
+    prescaler: u16,
+    seg1: u8,
+    seg2: u8,
+    sjw: u8,
+    loopback: bool,
+    silent: bool,
+}
+
+impl BitTiming {
+    /// Creates bit timing parameters from their individual values.
+    ///
+    /// All values are given in their natural unit (not in the "minus one" form used by the
+    /// register):
+    ///
+    /// - `prescaler`: length of a time quantum in peripheral clock cycles (`1..=1024`).
+    /// - `seg1`: length of time segment 1 in time quanta (`1..=16`).
+    /// - `seg2`: length of time segment 2 in time quanta (`1..=8`).
+    /// - `sjw`: resynchronization jump width in time quanta (`1..=4`).
+    ///
+    /// Returns `None` if any of the values are out of range.
+    pub const fn new(prescaler: u16, seg1: u8, seg2: u8, sjw: u8) -> Option<Self> {
+        if prescaler == 0 || prescaler as u32 > MAX_PRESCALER {
+            return None;
+        }
+        if seg1 == 0 || seg1 as u32 > MAX_SEG1 {
+            return None;
+        }
+        if seg2 == 0 || seg2 as u32 > MAX_SEG2 {
+            return None;
+        }
+        if sjw == 0 || sjw > MAX_SJW {
+            return None;
+        }
+
+        Some(Self {
+            prescaler,
+            seg1,
+            seg2,
+            sjw,
+            loopback: false,
+            silent: false,
+        })
+    }
+
+    /// Decodes the contents of a `CAN_BTR` register.
+    ///
+    /// This is the inverse of [`BitTiming::btr`] and can be used to make sense of hard-coded `btr`
+    /// values. Reserved bits are ignored.
+    ///
+    /// ```
+    /// use bxcan::BitTiming;
+    ///
+    /// let timing = BitTiming::from_btr(0x001c_0003);
+    /// assert_eq!(timing.prescaler(), 4);
+    /// assert_eq!(timing.seg1(), 13);
+    /// assert_eq!(timing.seg2(), 2);
+    /// assert_eq!(timing.sjw(), 1);
+    /// assert_eq!(timing.bitrate(8_000_000), 125_000);
+    /// assert_eq!(timing.sample_point(), 875);
+    /// ```
+    pub const fn from_btr(btr: u32) -> Self {
+        Self {
+            prescaler: (btr & 0x3ff) as u16 + 1,
+            seg1: ((btr >> 16) & 0xf) as u8 + 1,
+            seg2: ((btr >> 20) & 0x7) as u8 + 1,
+            sjw: ((btr >> 24) & 0x3) as u8 + 1,
+            loopback: btr & (1 << 30) != 0,
+            silent: btr & (1 << 31) != 0,
+        }
+    }
+
+    /// Computes the bit timing that comes closest to the requested parameters.
+    ///
+    /// # Parameters
+    ///
+    /// - `clock_hz`: the input clock of the CAN peripheral (*not* the CPU clock). This is the clock
+    ///   of the peripheral bus the CAN peripheral is attached to (eg. APB1).
+    /// - `bitrate`: the desired bitrate in bit/s.
+    /// - `sample_point`: the desired sample point in per mille of the bit time. This should
+    ///   normally be 875 (87.5%).
+    /// - `sjw`: the resynchronization jump width in time quanta (`1..=4`). This should normally
+    ///   be 1.
+    ///
+    /// All prescaler and time segment combinations supported by the peripheral are searched. The
+    /// result with the smallest bitrate error is returned, with ties broken first by the smallest
+    /// sample point error, then by the largest number of time quanta per bit. Both time segments
+    /// are at least `sjw` time quanta long.
+    ///
+    /// Results whose bitrate deviates by more than 1.58% (the oscillator tolerance permitted by the
+    /// CAN specification) are never returned.
+    ///
+    /// Use [`BitTimingMatch::is_exact`] to check whether the requested bitrate can be reached
+    /// exactly.
+    ///
+    /// Since this is a `const fn`, the result can be computed and checked at compile time:
+    ///
+    /// ```
+    /// use bxcan::BitTiming;
+    ///
+    /// const BTR: u32 = match BitTiming::calculate(8_000_000, 125_000, 875, 1) {
+    ///     Ok(m) if m.is_exact() => m.timing().btr(),
+    ///     _ => panic!("no exact bit timing for 125 kbit/s"),
+    /// };
+    /// # assert_eq!(BTR, 0x001c_0003);
+    /// ```
+    pub const fn calculate(
+        clock_hz: u32,
+        bitrate: u32,
+        sample_point: u16,
+        sjw: u8,
+    ) -> Result<BitTimingMatch, BitTimingError> {
+        if bitrate == 0 || sample_point == 0 || sample_point >= 1000 {
+            return Err(BitTimingError::InvalidParameter);
+        }
+        if sjw == 0 || sjw > MAX_SJW {
+            return Err(BitTimingError::InvalidParameter);
+        }
+
+        let clock = clock_hz as u64;
+        let bitrate = bitrate as u64;
+        let sample_point = sample_point as u64;
+        let min_seg = sjw as u32;
+
+        // Best candidate so far and its errors, stored as fractions:
+        // - bitrate error: `rate_err_num / rate_err_den` in bit/s
+        // - sample point error: `sp_err_num / sp_err_den` in per mille
+        let mut best: Option<BitTiming> = None;
+        let (mut rate_err_num, mut rate_err_den) = (0, 1);
+        let (mut sp_err_num, mut sp_err_den) = (0, 1);
+
+        // Prefer more time quanta per bit by searching from the top: a candidate only replaces
+        // the current best one if it is strictly better.
+        let mut tq = 1 + MAX_SEG1 + MAX_SEG2;
+        while tq > 2 * min_seg {
+            // Pick the time segments that get closest to the requested sample point.
+            let mut seg1 = ((sample_point * tq as u64 + 500) / 1000) as u32;
+            seg1 = seg1.saturating_sub(1);
+            if seg1 < min_seg {
+                seg1 = min_seg;
+            }
+            if tq - 1 - seg1 < min_seg {
+                seg1 = tq - 1 - min_seg;
+            }
+            if seg1 > MAX_SEG1 {
+                seg1 = MAX_SEG1;
+            }
+            if tq - 1 - seg1 > MAX_SEG2 {
+                seg1 = tq - 1 - MAX_SEG2;
+            }
+            let seg2 = tq - 1 - seg1;
+
+            let feasible = seg1 >= min_seg && seg1 <= MAX_SEG1 && seg2 >= min_seg;
+            if feasible {
+                // Both the prescaler rounded down and rounded up may be the closest match.
+                let floor = clock / (bitrate * tq as u64);
+                let mut prescaler = if floor == 0 { 1 } else { floor };
+                while prescaler < floor + 2 && prescaler <= MAX_PRESCALER as u64 {
+                    let cycles = prescaler * tq as u64;
+                    let rate_num = clock.abs_diff(bitrate * cycles);
+                    let sp_num = (1000 * (1 + seg1 as u64)).abs_diff(sample_point * tq as u64);
+
+                    let in_tolerance = rate_num * 10_000 <= MAX_BITRATE_ERROR * bitrate * cycles;
+                    let is_better = match best {
+                        _ if !in_tolerance => false,
+                        None => true,
+                        Some(_) => {
+                            let lhs = rate_num * rate_err_den;
+                            let rhs = rate_err_num * cycles;
+                            lhs < rhs
+                                || (lhs == rhs && sp_num * sp_err_den < sp_err_num * tq as u64)
+                        }
+                    };
+                    if is_better {
+                        best = Some(BitTiming {
+                            prescaler: prescaler as u16,
+                            seg1: seg1 as u8,
+                            seg2: seg2 as u8,
+                            sjw,
+                            loopback: false,
+                            silent: false,
+                        });
+                        rate_err_num = rate_num;
+                        rate_err_den = cycles;
+                        sp_err_num = sp_num;
+                        sp_err_den = tq as u64;
+                    }
+
+                    prescaler += 1;
+                }
+            }
+
+            tq -= 1;
+        }
+
+        match best {
+            Some(timing) => {
+                let cycles = timing.prescaler as u64 * timing.nominal_bit_time() as u64;
+                // The relative error is `clock / (cycles * bitrate) - 1`. Errors below 0.5 ppm are
+                // rounded up, so that only exact matches report no error.
+                let requested_cycles = cycles * bitrate;
+                let (diff, negative) = if clock >= requested_cycles {
+                    (clock - requested_cycles, false)
+                } else {
+                    (requested_cycles - clock, true)
+                };
+                let mut ppm = ((diff * 1_000_000 + requested_cycles / 2) / requested_cycles) as i32;
+                if ppm == 0 && diff != 0 {
+                    ppm = 1;
+                }
+                Ok(BitTimingMatch {
+                    timing,
+                    bitrate: (clock / cycles) as u32,
+                    exact: rate_err_num == 0,
+                    bitrate_error_ppm: if negative { -ppm } else { ppm },
+                })
+            }
+            None => Err(BitTimingError::NoSolution),
+        }
+    }
+
+    /// Returns the baud rate prescaler, which is the length of a time quantum in peripheral clock
+    /// cycles.
+    #[inline]
+    pub const fn prescaler(&self) -> u16 {
+        self.prescaler
+    }
+
+    /// Returns the length of time segment 1 in time quanta.
+    #[inline]
+    pub const fn seg1(&self) -> u8 {
+        self.seg1
+    }
+
+    /// Returns the length of time segment 2 in time quanta.
+    #[inline]
+    pub const fn seg2(&self) -> u8 {
+        self.seg2
+    }
+
+    /// Returns the resynchronization jump width in time quanta.
+    #[inline]
+    pub const fn sjw(&self) -> u8 {
+        self.sjw
+    }
+
+    /// Returns the nominal bit time in time quanta.
+    ///
+    /// This is the sum of the synchronization segment (1 tq) and both time segments.
+    #[inline]
+    pub const fn nominal_bit_time(&self) -> u8 {
+        1 + self.seg1 + self.seg2
+    }
+
+    /// Returns `true` if the loopback mode bit is set.
+    ///
+    /// This is only ever the case for values decoded by [`BitTiming::from_btr`].
+    #[inline]
+    pub const fn loopback(&self) -> bool {
+        self.loopback
+    }
+
+    /// Returns `true` if the silent mode bit is set.
+    ///
+    /// This is only ever the case for values decoded by [`BitTiming::from_btr`].
+    #[inline]
+    pub const fn silent(&self) -> bool {
+        self.silent
+    }
+
+    /// Returns the resulting bitrate in bit/s (rounded down) for the given peripheral clock.
+    pub const fn bitrate(&self, clock_hz: u32) -> u32 {
+        clock_hz / (self.prescaler as u32 * self.nominal_bit_time() as u32)
+    }
+
+    /// Returns the sample point in per mille of the nominal bit time (rounded to the nearest
+    /// value).
+    pub const fn sample_point(&self) -> u16 {
+        let tq = self.nominal_bit_time() as u16;
+        ((1 + self.seg1 as u16) * 1000 + tq / 2) / tq
+    }
+
+    /// Returns the value of the `CAN_BTR` register encoding these parameters.
+    ///
+    /// This includes the silent and loopback mode bits, which are ignored by
+    /// [`CanBuilder::set_bit_timing`] and [`CanConfig::set_bit_timing`].
+    ///
+    /// [`CanBuilder::set_bit_timing`]: crate::CanBuilder::set_bit_timing
+    /// [`CanConfig::set_bit_timing`]: crate::CanConfig::set_bit_timing
+    pub const fn btr(&self) -> u32 {
+        (self.silent as u32) << 31
+            | (self.loopback as u32) << 30
+            | (self.sjw as u32 - 1) << 24
+            | (self.seg2 as u32 - 1) << 20
+            | (self.seg1 as u32 - 1) << 16
+            | (self.prescaler as u32 - 1)
+    }
+}
+
+/// Result of a [`BitTiming::calculate`] call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct BitTimingMatch {
+    timing: BitTiming,
+    bitrate: u32,
+    exact: bool,
+    bitrate_error_ppm: i32,
+}
+
+impl BitTimingMatch {
+    /// Returns the calculated bit timing parameters.
+    #[inline]
+    pub const fn timing(&self) -> BitTiming {
+        self.timing
+    }
+
+    /// Returns the bitrate (in bit/s, rounded down) resulting from the calculated parameters.
+    #[inline]
+    pub const fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    /// Returns the deviation of the resulting bitrate from the requested one, in parts per million
+    /// of the requested bitrate.
+    ///
+    /// The value is rounded to the nearest integer, except that it is only 0 if
+    /// [`is_exact`](Self::is_exact) returns `true`.
+    #[inline]
+    pub const fn bitrate_error_ppm(&self) -> i32 {
+        self.bitrate_error_ppm
+    }
+
+    /// Returns `true` if the requested bitrate is reached exactly.
+    #[inline]
+    pub const fn is_exact(&self) -> bool {
+        self.exact
+    }
+}
+
+/// Error returned by [`BitTiming::calculate`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum BitTimingError {
+    /// The bitrate is 0, the sample point is not in the range `1..1000`, or the SJW is not in the
+    /// range `1..=4`.
+    InvalidParameter,
+    /// No combination of prescaler and time segments gets within 1.58% of the requested bitrate.
+    ///
+    /// This happens when the peripheral clock is too slow or too fast for the requested bitrate,
+    /// or when it can not be divided down to the bitrate accurately enough.
+    NoSolution,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(clock_hz: u32, bitrate: u32) -> BitTimingMatch {
+        BitTiming::calculate(clock_hz, bitrate, 875, 1).unwrap()
+    }
+
+    #[test]
+    fn new_checks_ranges() {
+        assert!(BitTiming::new(1, 1, 1, 1).is_some());
+        assert!(BitTiming::new(1024, 16, 8, 4).is_some());
+        assert!(BitTiming::new(0, 1, 1, 1).is_none());
+        assert!(BitTiming::new(1025, 1, 1, 1).is_none());
+        assert!(BitTiming::new(1, 17, 1, 1).is_none());
+        assert!(BitTiming::new(1, 1, 9, 1).is_none());
+        assert!(BitTiming::new(1, 1, 1, 5).is_none());
+    }
+
+    #[test]
+    fn btr_encoding() {
+        assert_eq!(BitTiming::new(1, 1, 1, 1).unwrap().btr(), 0);
+        assert_eq!(BitTiming::new(1024, 16, 8, 4).unwrap().btr(), 0x037f_03ff);
+        assert_eq!(BitTiming::new(4, 13, 2, 1).unwrap().btr(), 0x001c_0003);
+    }
+
+    // Checked against the tables from <http://www.bittiming.can-wiki.info/> (bxCAN, 87.5%,
+    // SJW 1).
+    #[test]
+    fn can_wiki_tables() {
+        let m = calc(8_000_000, 125_000);
+        assert!(m.is_exact());
+        assert_eq!(m.bitrate(), 125_000);
+        assert_eq!(m.timing().btr(), 0x001c_0003);
+
+        let m = calc(8_000_000, 500_000);
+        assert!(m.is_exact());
+        assert_eq!(m.timing().btr(), 0x001c_0000);
+
+        // 18 tq only reach a sample point of 88.9%, so 8 tq are preferred.
+        let m = calc(36_000_000, 500_000);
+        assert!(m.is_exact());
+        assert_eq!(m.timing().btr(), 0x0005_0008);
+
+        let m = calc(36_000_000, 125_000);
+        assert!(m.is_exact());
+        assert_eq!(m.timing().btr(), 0x001c_0011);
+
+        let m = calc(48_000_000, 1_000_000);
+        assert!(m.is_exact());
+        assert_eq!(m.timing().btr(), 0x001c_0002);
+    }
+
+    #[test]
+    fn inexact() {
+        let m = calc(8_000_000, 33_333);
+        assert!(!m.is_exact());
+        assert_eq!(m.bitrate(), 33_333);
+        // 33 333.33 bit/s
+        assert_eq!(m.bitrate_error_ppm(), 10);
+
+        let m = calc(36_000_000, 700_000);
+        assert!(!m.is_exact());
+        assert_ne!(m.bitrate_error_ppm(), 0);
+    }
+
+    #[test]
+    fn sjw_limits_segments() {
+        let m = BitTiming::calculate(36_000_000, 500_000, 875, 4).unwrap();
+        assert!(m.is_exact());
+        assert!(m.timing().seg1() >= 4);
+        assert!(m.timing().seg2() >= 4);
+        assert_eq!(m.timing().sjw(), 4);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            BitTiming::calculate(8_000_000, 0, 875, 1),
+            Err(BitTimingError::InvalidParameter)
+        );
+        assert_eq!(
+            BitTiming::calculate(8_000_000, 125_000, 1000, 1),
+            Err(BitTimingError::InvalidParameter)
+        );
+        assert_eq!(
+            BitTiming::calculate(8_000_000, 125_000, 875, 5),
+            Err(BitTimingError::InvalidParameter)
+        );
+        assert_eq!(
+            BitTiming::calculate(1_000_000, 1_000_000, 875, 1),
+            Err(BitTimingError::NoSolution)
+        );
+        assert_eq!(
+            BitTiming::calculate(100_000_000, 1_000, 875, 1),
+            Err(BitTimingError::NoSolution)
+        );
+        // 8 MHz / 1.1 Mbit/s is about 7.3 tq, which is off by 4% with 7 tq.
+        assert_eq!(
+            BitTiming::calculate(8_000_000, 1_100_000, 875, 1),
+            Err(BitTimingError::NoSolution)
+        );
+    }
+
+    #[test]
+    fn decode_btr() {
+        let timing = BitTiming::from_btr(0x001c_0003);
+        assert_eq!(timing, BitTiming::new(4, 13, 2, 1).unwrap());
+        assert_eq!(timing.nominal_bit_time(), 16);
+        assert_eq!(timing.bitrate(8_000_000), 125_000);
+        assert_eq!(timing.bitrate(32_000_000), 500_000);
+        assert_eq!(timing.sample_point(), 875);
+
+        let timing = BitTiming::from_btr(0xc37f_03ff);
+        assert_eq!(timing.prescaler(), 1024);
+        assert_eq!(timing.seg1(), 16);
+        assert_eq!(timing.seg2(), 8);
+        assert_eq!(timing.sjw(), 4);
+        assert!(timing.loopback());
+        assert!(timing.silent());
+        assert_eq!(timing.sample_point(), 680);
+
+        // Reserved bits are dropped.
+        assert_eq!(BitTiming::from_btr(0xffff_ffff).btr(), 0xc37f_03ff);
+        assert_eq!(BitTiming::from_btr(0x4005_0008).btr(), 0x4005_0008);
+    }
+
+    #[test]
+    fn const_eval() {
+        const M: BitTimingMatch = match BitTiming::calculate(8_000_000, 125_000, 875, 1) {
+            Ok(m) => m,
+            Err(_) => panic!(),
+        };
+        assert_eq!(M.timing().btr(), 0x001c_0003);
+    }
+}
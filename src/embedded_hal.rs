@@ -1,6 +1,6 @@
 //! `embedded_hal` trait impls.
 
-use crate::{Can, Data, ExtendedId, Frame, Id, Instance, OverrunError, StandardId};
+use crate::{Can, CanError, Data, ExtendedId, Frame, Id, Instance, OverrunError, StandardId};
 
 use embedded_can_04 as can;
 
@@ -25,36 +25,98 @@ where
     }
 }
 
+impl<I> can::blocking::Can for Can<I>
+where
+    I: Instance,
+{
+    type Frame = Frame;
+
+    type Error = CanError;
+
+    /// Waits for a free transmit mailbox and puts the frame into it.
+    ///
+    /// Like [`Tx::transmit`](crate::Tx::transmit), this also waits while a pending frame has the
+    /// same or a higher priority, to keep frames with the same identifier in order.
+    ///
+    /// Unlike the non-blocking implementation, this never replaces a pending frame of lower
+    /// priority, since the trait has no way to hand it back to the caller.
+    fn transmit(&mut self, frame: &Self::Frame) -> Result<(), Self::Error> {
+        loop {
+            let can = self.registers();
+            if can.esr.read().boff().bit_is_set() {
+                return Err(CanError::BusOff);
+            }
+
+            let tsr = can.tsr.read();
+            if tsr.tme0().bit_is_clear() && tsr.tme1().bit_is_clear() && tsr.tme2().bit_is_clear() {
+                continue;
+            }
+
+            // A mailbox is free, so no pending frame is dequeued. This still blocks while a
+            // pending frame has the same or a higher priority than `frame`.
+            match self.transmit(frame) {
+                Ok(_) => return Ok(()),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => match e {},
+            }
+        }
+    }
+
+    /// Waits for a frame to arrive in any of the receive FIFOs.
+    fn receive(&mut self) -> Result<Self::Frame, Self::Error> {
+        loop {
+            match self.receive() {
+                Ok(frame) => return Ok(frame),
+                Err(nb::Error::Other(e)) => return Err(e.into()),
+                Err(nb::Error::WouldBlock) => {
+                    if self.registers().esr.read().boff().bit_is_set() {
+                        return Err(CanError::BusOff);
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl can::Error for OverrunError {
     fn kind(&self) -> can::ErrorKind {
         can::ErrorKind::Overrun
     }
 }
 
+impl can::Error for CanError {
+    fn kind(&self) -> can::ErrorKind {
+        match self {
+            CanError::Overrun(_) => can::ErrorKind::Overrun,
+            CanError::BusOff => can::ErrorKind::Other,
+        }
+    }
+}
+
+fn id_from_hal(id: can::Id) -> Option<Id> {
+    Some(match id {
+        can::Id::Standard(id) => Id::Standard(StandardId::new(id.as_raw())?),
+        can::Id::Extended(id) => Id::Extended(ExtendedId::new(id.as_raw())?),
+    })
+}
+
+fn id_to_hal(id: Id) -> Option<can::Id> {
+    Some(match id {
+        Id::Standard(id) => can::Id::Standard(can::StandardId::new(id.as_raw())?),
+        Id::Extended(id) => can::Id::Extended(can::ExtendedId::new(id.as_raw())?),
+    })
+}
+
 impl can::Frame for Frame {
     fn new(id: impl Into<can::Id>, data: &[u8]) -> Option<Self> {
-        let id = match id.into() {
-            can::Id::Standard(id) => unsafe {
-                Id::Standard(StandardId::new_unchecked(id.as_raw()))
-            },
-            can::Id::Extended(id) => unsafe {
-                Id::Extended(ExtendedId::new_unchecked(id.as_raw()))
-            },
-        };
+        let id = id_from_hal(id.into())?;
 
         let data = Data::new(data)?;
         Some(Frame::new_data(id, data))
     }
 
     fn new_remote(id: impl Into<can::Id>, dlc: usize) -> Option<Self> {
-        let id = match id.into() {
-            can::Id::Standard(id) => unsafe {
-                Id::Standard(StandardId::new_unchecked(id.as_raw()))
-            },
-            can::Id::Extended(id) => unsafe {
-                Id::Extended(ExtendedId::new_unchecked(id.as_raw()))
-            },
-        };
+        let id = id_from_hal(id.into())?;
 
         if dlc <= 8 {
             Some(Frame::new_remote(id, dlc as u8))
@@ -75,14 +137,8 @@ impl can::Frame for Frame {
 
     #[inline]
     fn id(&self) -> can::Id {
-        match self.id() {
-            Id::Standard(id) => unsafe {
-                can::Id::Standard(can::StandardId::new_unchecked(id.as_raw()))
-            },
-            Id::Extended(id) => unsafe {
-                can::Id::Extended(can::ExtendedId::new_unchecked(id.as_raw()))
-            },
-        }
+        // Both crates accept the same ID ranges.
+        id_to_hal(self.id()).expect("identifier out of range")
     }
 
     #[inline]
@@ -98,3 +154,60 @@ impl can::Frame for Frame {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Mask32;
+    use crate::sim::Sim;
+
+    #[test]
+    fn frame_conversion() {
+        let id = can::ExtendedId::new(0x1234_5678).unwrap();
+        let frame: Frame = can::Frame::new(id, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            frame.id(),
+            Id::Extended(ExtendedId::new(0x1234_5678).unwrap())
+        );
+        assert_eq!(can::Frame::id(&frame), can::Id::Extended(id));
+        assert_eq!(can::Frame::data(&frame), &[1, 2, 3]);
+
+        let id = can::StandardId::MAX;
+        let frame: Frame = can::Frame::new_remote(id, 8).unwrap();
+        assert_eq!(frame.id(), Id::Standard(StandardId::MAX));
+        assert_eq!(can::Frame::id(&frame), can::Id::Standard(id));
+        assert!(can::Frame::is_remote_frame(&frame));
+        assert_eq!(can::Frame::dlc(&frame), 8);
+
+        assert!(<Frame as can::Frame>::new(id, &[0; 9]).is_none());
+        assert!(<Frame as can::Frame>::new_remote(id, 9).is_none());
+    }
+
+    #[test]
+    fn blocking_transmit_same_id() {
+        let (mut sim, p) = Sim::new();
+        let mut can = Can::builder(p.can1)
+            .set_loopback(true)
+            .set_silent(true)
+            .enable();
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, crate::Fifo::Fifo0, Mask32::accept_all());
+
+        // The second frame has to wait until the first one was transmitted. This is where the
+        // blocking implementation keeps retrying.
+        let frames = [1, 2].map(|data| Frame::new_data(StandardId::new(5).unwrap(), [data]));
+        can::blocking::Can::transmit(&mut can, &frames[0]).unwrap();
+        assert!(matches!(
+            can::nb::Can::transmit(&mut can, &frames[1]),
+            Err(nb::Error::WouldBlock)
+        ));
+        assert!(sim.step());
+        can::blocking::Can::transmit(&mut can, &frames[1]).unwrap();
+        sim.run();
+
+        for frame in &frames {
+            assert_eq!(can::blocking::Can::receive(&mut can).unwrap(), *frame);
+        }
+    }
+}
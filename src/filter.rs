@@ -1,9 +1,15 @@
 //! Filter bank API.
 
+mod plan;
+
 use core::marker::PhantomData;
 
 use crate::pac::can::RegisterBlock;
-use crate::{ExtendedId, Fifo, FilterOwner, Id, Instance, MasterInstance, StandardId};
+use crate::{ExtendedId, Fifo, FilterOwner, Frame, Id, Instance, MasterInstance, StandardId};
+
+use plan::RangeBlocks;
+
+pub use plan::{AcceptRule, FilterPlan, FilterPlanner, PlanError};
 
 const F32_RTR: u32 = 0b010; // set the RTR bit to match remote frames
 const F32_IDE: u32 = 0b100; // set the IDE bit to match extended identifiers
@@ -12,7 +18,8 @@ const F16_IDE: u16 = 0b01000;
 
 /// A 16-bit filter list entry.
 ///
-/// This can match data and remote frames using standard IDs.
+/// This can match data and remote frames using standard IDs, or extended IDs by their upper 14
+/// bits.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
 pub struct ListEntry16(u16);
@@ -25,7 +32,9 @@ pub struct ListEntry16(u16);
 pub struct ListEntry32(u32);
 
 /// A 16-bit identifier mask.
-#[derive(Debug, Copy, Clone)]
+///
+/// This can match standard IDs, or extended IDs by their upper 14 bits.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
 pub struct Mask16 {
     id: u16,
@@ -33,7 +42,7 @@ pub struct Mask16 {
 }
 
 /// A 32-bit identifier mask.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
 pub struct Mask32 {
     id: u32,
@@ -52,6 +61,24 @@ impl ListEntry16 {
     pub fn remote_frames_with_id(id: StandardId) -> Self {
         Self(id.as_raw() << 5 | F16_RTR)
     }
+
+    /// Creates a filter list entry that accepts data frames with the given extended ID.
+    ///
+    /// The 16-bit format only holds bits 28 to 15 of an extended ID, so this entry accepts every
+    /// extended ID that shares those bits with `id`. Bits 14 to 0 are ignored.
+    ///
+    /// This entry will *not* accept remote frames, or standard frames.
+    pub fn data_frames_with_ext_id(id: ExtendedId) -> Self {
+        Self(ext_id_16(id) | F16_IDE)
+    }
+
+    /// Creates a filter list entry that accepts remote frames with the given extended ID.
+    ///
+    /// The 16-bit format only holds bits 28 to 15 of an extended ID, so this entry accepts every
+    /// extended ID that shares those bits with `id`. Bits 14 to 0 are ignored.
+    pub fn remote_frames_with_ext_id(id: ExtendedId) -> Self {
+        Self(ext_id_16(id) | F16_IDE | F16_RTR)
+    }
 }
 
 impl ListEntry32 {
@@ -100,6 +127,55 @@ impl Mask16 {
         }
     }
 
+    /// Creates a 16-bit identifier mask that accepts all frames with the given extended
+    /// ID and mask combination.
+    ///
+    /// Filter logic: `frame_accepted = (incoming_id & mask) == (id & mask)`
+    ///
+    /// The 16-bit format only holds bits 28 to 15 of an extended ID, so bits 14 to 0 of both
+    /// `id` and `mask` are ignored and never compared. A mask of `0x1FFF_8000` matches all
+    /// extended IDs that share their upper 14 bits with `id`.
+    ///
+    /// Both data and remote frames with `id` will be accepted. Standard frames will be rejected.
+    pub fn frames_with_ext_id(id: ExtendedId, mask: ExtendedId) -> Self {
+        Self {
+            id: ext_id_16(id) | F16_IDE,
+            mask: ext_id_16(mask) | F16_IDE, // also require IDE = 1
+        }
+    }
+
+    /// Creates the smallest set of 16-bit identifier masks that together accept exactly the
+    /// standard IDs from `first` to `last`, inclusive.
+    ///
+    /// Both data and remote frames will be accepted. Any extended frames will be rejected. If
+    /// `first` is greater than `last`, no masks are returned.
+    pub fn std_id_range(first: StandardId, last: StandardId) -> impl Iterator<Item = Self> {
+        RangeBlocks::new(first.as_raw().into(), last.as_raw().into(), 11).map(|(id, mask)| Self {
+            id: (id as u16) << 5,
+            mask: (mask as u16) << 5 | F16_IDE,
+        })
+    }
+
+    /// Computes the tightest single mask that accepts all of the given standard IDs.
+    ///
+    /// Returns the mask, and the number of other standard IDs it accepts as well. Returns `None`
+    /// if `ids` is empty.
+    ///
+    /// Both data and remote frames will be accepted. Any extended frames will be rejected.
+    pub fn covering(ids: &[StandardId]) -> Option<(Self, u64)> {
+        let first = ids.first()?.as_raw();
+        let differing = ids.iter().fold(0, |acc, id| acc | (id.as_raw() ^ first));
+        let mask = !differing & StandardId::MAX.as_raw();
+        let accepted = 1 << (11 - mask.count_ones());
+        Some((
+            Self::frames_with_std_id(
+                StandardId::new(first & mask).unwrap(),
+                StandardId::new(mask).unwrap(),
+            ),
+            accepted - distinct(ids),
+        ))
+    }
+
     /// Make the filter accept data frames only.
     pub fn data_frames_only(&mut self) -> &mut Self {
         self.id &= !F16_RTR; // RTR = 0
@@ -153,6 +229,84 @@ impl Mask32 {
         }
     }
 
+    /// Creates the smallest set of 32-bit identifier masks that together accept exactly the
+    /// standard IDs from `first` to `last`, inclusive.
+    ///
+    /// Both data and remote frames will be accepted. Extended frames will be rejected. If `first`
+    /// is greater than `last`, no masks are returned.
+    pub fn std_id_range(first: StandardId, last: StandardId) -> impl Iterator<Item = Self> {
+        RangeBlocks::new(first.as_raw().into(), last.as_raw().into(), 11).map(|(id, mask)| Self {
+            id: id << 21,
+            mask: mask << 21 | F32_IDE,
+        })
+    }
+
+    /// Creates the smallest set of 32-bit identifier masks that together accept exactly the
+    /// extended IDs from `first` to `last`, inclusive.
+    ///
+    /// Both data and remote frames will be accepted. Standard frames will be rejected. If `first`
+    /// is greater than `last`, no masks are returned.
+    pub fn ext_id_range(first: ExtendedId, last: ExtendedId) -> impl Iterator<Item = Self> {
+        RangeBlocks::new(first.as_raw(), last.as_raw(), 29).map(|(id, mask)| Self {
+            id: id << 3 | F32_IDE,
+            mask: mask << 3 | F32_IDE,
+        })
+    }
+
+    /// Computes the tightest single mask that accepts all of the given IDs.
+    ///
+    /// Returns the mask, and the number of other IDs it accepts as well. Standard and extended IDs
+    /// are counted separately, so a mask covering both kinds can accept a standard and an
+    /// extended frame with the same numeric ID. Returns `None` if `ids` is empty.
+    ///
+    /// Both data and remote frames will be accepted.
+    ///
+    /// # Example
+    ///
+    /// J1939 places the parameter group number (PGN) in bits 8 to 25 of an extended ID, and the
+    /// source address in the low 8 bits. Covering two source addresses that differ in every bit
+    /// accepts the PGN from any source:
+    ///
+    /// ```
+    /// use bxcan::filter::Mask32;
+    /// use bxcan::{ExtendedId, Id};
+    ///
+    /// let id = |raw| Id::Extended(ExtendedId::new(raw).unwrap());
+    /// let (mask, extra) = Mask32::covering(&[id(0x18FE_F100), id(0x18FE_F1FF)]).unwrap();
+    /// assert_eq!(mask, Mask32::frames_with_ext_id(
+    ///     ExtendedId::new(0x18FE_F100).unwrap(),
+    ///     ExtendedId::new(0x1FFF_FF00).unwrap(),
+    /// ));
+    /// assert_eq!(extra, 254);
+    /// ```
+    pub fn covering(ids: &[Id]) -> Option<(Self, u64)> {
+        // Register layout of the identifier, ignoring the RTR bit.
+        let raw = |id: &Id| match *id {
+            Id::Standard(id) => u32::from(id.as_raw()) << 21,
+            Id::Extended(id) => id.as_raw() << 3 | F32_IDE,
+        };
+        let first = raw(ids.first()?);
+        let differing = ids.iter().fold(0, |acc, id| acc | (raw(id) ^ first));
+        let mut mask = !differing & !(F32_RTR | 1);
+        if mask & F32_IDE != 0 && first & F32_IDE == 0 {
+            // Only standard IDs, whose EXID bits need not be compared.
+            mask &= !0x001F_FFF8;
+        }
+        let id = first & mask;
+
+        // Standard frames have the EXID bits cleared, so they only match if the mask does not
+        // require any of them to be set.
+        let ide_free = mask & F32_IDE == 0;
+        let mut accepted = 0;
+        if ide_free || id & F32_IDE != 0 {
+            accepted += 1 << (29 - (mask >> 3).count_ones());
+        }
+        if (ide_free || id & F32_IDE == 0) && id & 0x001F_FFF8 == 0 {
+            accepted += 1 << (11 - (mask >> 21).count_ones());
+        }
+        Some((Self { id, mask }, accepted - distinct(ids)))
+    }
+
     /// Make the filter accept data frames only.
     pub fn data_frames_only(&mut self) -> &mut Self {
         self.id &= !F32_RTR; // RTR = 0
@@ -168,8 +322,24 @@ impl Mask32 {
     }
 }
 
+/// Places bits 28 to 15 of an extended ID into the STID[10:0] and EXID[17:15] fields of the
+/// 16-bit filter format.
+fn ext_id_16(id: ExtendedId) -> u16 {
+    let raw = id.as_raw();
+    ((raw >> 18) << 5 | (raw >> 15) & 0b111) as u16
+}
+
+/// Returns the number of distinct items in `items`.
+fn distinct<T: PartialEq>(items: &[T]) -> u64 {
+    items
+        .iter()
+        .enumerate()
+        .filter(|(i, item)| !items[..*i].contains(item))
+        .count() as u64
+}
+
 /// The configuration of a filter bank.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
 pub enum BankConfig {
     List16([ListEntry16; 4]),
@@ -206,6 +376,304 @@ impl From<Mask32> for BankConfig {
     }
 }
 
+impl BankConfig {
+    /// Returns `true` for banks in identifier list mode, `false` for identifier mask mode.
+    fn is_list(&self) -> bool {
+        matches!(self, BankConfig::List16(_) | BankConfig::List32(_))
+    }
+
+    /// Returns `true` for banks using the 32-bit scale.
+    fn is_32bit(&self) -> bool {
+        matches!(self, BankConfig::List32(_) | BankConfig::Mask32(_))
+    }
+
+    /// Returns the number of filters in the bank, which is the number of filter match indices it
+    /// occupies.
+    fn len(&self) -> u8 {
+        match self {
+            BankConfig::List16(_) => 4,
+            BankConfig::List32(_) | BankConfig::Mask16(_) => 2,
+            BankConfig::Mask32(_) => 1,
+        }
+    }
+
+    /// Decodes the configuration from the mode and scale bits, and the FR1 and FR2 registers of a
+    /// bank.
+    fn from_registers(list: bool, wide: bool, fr1: u32, fr2: u32) -> Self {
+        let low = |fr: u32| fr as u16;
+        let high = |fr: u32| (fr >> 16) as u16;
+        match (list, wide) {
+            (true, false) => BankConfig::List16([
+                ListEntry16(low(fr1)),
+                ListEntry16(high(fr1)),
+                ListEntry16(low(fr2)),
+                ListEntry16(high(fr2)),
+            ]),
+            (true, true) => BankConfig::List32([ListEntry32(fr1), ListEntry32(fr2)]),
+            (false, false) => BankConfig::Mask16([
+                Mask16 {
+                    id: low(fr1),
+                    mask: high(fr1),
+                },
+                Mask16 {
+                    id: low(fr2),
+                    mask: high(fr2),
+                },
+            ]),
+            (false, true) => BankConfig::Mask32(Mask32 { id: fr1, mask: fr2 }),
+        }
+    }
+
+    /// Returns the values of the FR1 and FR2 registers of the bank.
+    fn registers(&self) -> (u32, u32) {
+        match *self {
+            BankConfig::List16([a, b, c, d]) => (
+                (u32::from(b.0) << 16) | u32::from(a.0),
+                (u32::from(d.0) << 16) | u32::from(c.0),
+            ),
+            BankConfig::List32([a, b]) => (a.0, b.0),
+            BankConfig::Mask16([a, b]) => (
+                (u32::from(a.mask) << 16) | u32::from(a.id),
+                (u32::from(b.mask) << 16) | u32::from(b.id),
+            ),
+            BankConfig::Mask32(a) => (a.id, a.mask),
+        }
+    }
+
+    /// Returns whether filter number `filter` of the bank accepts a frame with the given
+    /// identifier, in the format of the `RIR` register.
+    fn filter_accepts(&self, filter: u8, rir: u32) -> bool {
+        let id32 = rir & !1;
+        // STID[10:0], RTR, IDE, EXID[17:15]
+        let id16 = ((rir >> 21) << 5
+            | ((rir >> 1) & 1) << 4
+            | ((rir >> 2) & 1) << 3
+            | (rir >> 18) & 0b111) as u16;
+        match self {
+            BankConfig::List16(entries) => entries[usize::from(filter)].0 == id16,
+            BankConfig::List32(entries) => entries[usize::from(filter)].0 & !1 == id32,
+            BankConfig::Mask16(masks) => {
+                let mask = masks[usize::from(filter)];
+                (id16 ^ mask.id) & mask.mask == 0
+            }
+            BankConfig::Mask32(mask) => (id32 ^ mask.id) & mask.mask & !1 == 0,
+        }
+    }
+}
+
+/// Total number of filter banks on devices with the most filter banks.
+const MAX_FILTER_BANKS: u8 = 28;
+
+/// A software model of the filter banks assigned to a CAN peripheral.
+///
+/// This evaluates frames the same way the hardware does, which allows predicting whether a frame
+/// is accepted, into which FIFO, and with which filter match index (FMI). The banks are configured
+/// with the same methods as [`MasterFilters`] and [`SlaveFilters`].
+///
+/// Like in the hardware, disabled banks keep their configuration, which still counts towards the
+/// filter match indices of the following banks. Banks that have never been enabled are 16-bit
+/// mask banks assigned to FIFO 0, as after reset.
+///
+/// # Example
+///
+/// ```
+/// use bxcan::filter::{FilterSet, ListEntry32, Mask32};
+/// use bxcan::{Fifo, Frame, StandardId};
+///
+/// let id = StandardId::new(0x100).unwrap();
+/// let mut filters = FilterSet::new();
+/// filters
+///     .enable_bank(0, Fifo::Fifo0, Mask32::accept_all())
+///     .enable_bank(1, Fifo::Fifo0, [ListEntry32::data_frames_with_id(id); 2]);
+///
+/// // List filters take priority over mask filters.
+/// let accepted = filters.evaluate(&Frame::new_data(id, [])).unwrap();
+/// assert_eq!(accepted.bank(), 1);
+/// assert_eq!(accepted.filter_match_index(), 1);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct FilterSet {
+    start_idx: u8,
+    banks: [(Fifo, BankConfig, bool); MAX_FILTER_BANKS as usize],
+}
+
+impl FilterSet {
+    /// Creates a model of the filter banks of a master or standalone peripheral, with all banks
+    /// disabled.
+    pub fn new() -> Self {
+        Self::new_slave(0)
+    }
+
+    /// Creates a model of the filter banks of a slave peripheral, with all banks disabled.
+    ///
+    /// `split_index` is the index of the first bank assigned to the slave peripheral, as passed
+    /// to [`MasterFilters::set_split`]. Filter match indices start at this bank.
+    pub fn new_slave(split_index: u8) -> Self {
+        assert!(split_index <= MAX_FILTER_BANKS);
+        let reset = BankConfig::Mask16([Mask16::accept_all(); 2]);
+        Self {
+            start_idx: split_index,
+            banks: [(Fifo::Fifo0, reset, false); MAX_FILTER_BANKS as usize],
+        }
+    }
+
+    fn bank_mut(&mut self, index: u8) -> &mut (Fifo, BankConfig, bool) {
+        assert!((self.start_idx..MAX_FILTER_BANKS).contains(&index));
+        &mut self.banks[usize::from(index)]
+    }
+
+    /// Disables all enabled filter banks.
+    pub fn clear(&mut self) -> &mut Self {
+        for (_, _, active) in &mut self.banks {
+            *active = false;
+        }
+        self
+    }
+
+    /// Disables a filter bank.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn disable_bank(&mut self, index: u8) -> &mut Self {
+        self.bank_mut(index).2 = false;
+        self
+    }
+
+    /// Configures a filter bank according to `config` and enables it.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn enable_bank(
+        &mut self,
+        index: u8,
+        fifo: Fifo,
+        config: impl Into<BankConfig>,
+    ) -> &mut Self {
+        *self.bank_mut(index) = (fifo, config.into(), true);
+        self
+    }
+
+    /// Disables all banks, and enables the banks of `plan`, starting at the first bank of the set.
+    ///
+    /// Panics if the plan does not fit into the banks of the set.
+    pub fn apply_plan(&mut self, plan: &FilterPlan) -> &mut Self {
+        self.clear();
+        for (index, (fifo, config)) in (self.start_idx..).zip(plan.banks()) {
+            self.enable_bank(index, *fifo, *config);
+        }
+        self
+    }
+
+    /// Returns the filter match index of filter number `filter` in bank `index`.
+    ///
+    /// Filters are numbered within their bank in the order they appear in the [`BankConfig`].
+    /// The index is only meaningful for frames in the FIFO the bank is assigned to.
+    ///
+    /// Panics if `index` or `filter` is out of bounds.
+    pub fn filter_match_index(&self, index: u8, filter: u8) -> u8 {
+        let (fifo, config, _) = &self.banks[usize::from(index)];
+        assert!(index >= self.start_idx && filter < config.len());
+
+        let preceding: u8 = self.banks[usize::from(self.start_idx)..usize::from(index)]
+            .iter()
+            .filter(|(bank_fifo, _, _)| bank_fifo == fifo)
+            .map(|(_, config, _)| config.len())
+            .sum();
+        preceding + filter
+    }
+
+    /// Determines whether the peripheral accepts `frame`, and if so, which filter accepts it.
+    ///
+    /// When several filters accept the frame, the hardware picks one by the following rules, in
+    /// order:
+    ///
+    /// 1. 32-bit filters take priority over 16-bit filters.
+    /// 2. Identifier list filters take priority over identifier mask filters.
+    /// 3. Filters in banks with a lower index take priority over those with a higher index, and
+    ///    within a bank, filters are considered in order.
+    pub fn evaluate(&self, frame: &Frame) -> Option<FilterMatch> {
+        self.evaluate_raw(frame.id.0)
+    }
+
+    fn evaluate_raw(&self, rir: u32) -> Option<FilterMatch> {
+        let mut best: Option<FilterMatch> = None;
+        let mut next_fmi = [0u8; 2];
+        for (index, (fifo, config, active)) in self.banks.iter().enumerate() {
+            let index = index as u8;
+            if index < self.start_idx {
+                continue;
+            }
+
+            let fmi = &mut next_fmi[*fifo as usize];
+            if *active {
+                let priority = |m: &FilterMatch| (!m.is_32bit, !m.is_list);
+                for filter in (0..config.len()).filter(|&f| config.filter_accepts(f, rir)) {
+                    let candidate = FilterMatch {
+                        fifo: *fifo,
+                        bank: index,
+                        filter,
+                        filter_match_index: *fmi + filter,
+                        is_list: config.is_list(),
+                        is_32bit: config.is_32bit(),
+                    };
+                    // Among filters of the same kind, the first one wins.
+                    if !matches!(&best, Some(best) if priority(best) <= priority(&candidate)) {
+                        best = Some(candidate);
+                    }
+                }
+            }
+            *fmi += config.len();
+        }
+        best
+    }
+}
+
+impl Default for FilterSet {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The filter accepting a frame, as determined by [`FilterSet::evaluate`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct FilterMatch {
+    fifo: Fifo,
+    bank: u8,
+    filter: u8,
+    filter_match_index: u8,
+    is_list: bool,
+    is_32bit: bool,
+}
+
+impl FilterMatch {
+    /// Returns the receive FIFO the frame is stored in.
+    #[inline]
+    pub fn fifo(&self) -> Fifo {
+        self.fifo
+    }
+
+    /// Returns the index of the filter bank containing the filter.
+    #[inline]
+    pub fn bank(&self) -> u8 {
+        self.bank
+    }
+
+    /// Returns the number of the filter within its bank.
+    #[inline]
+    pub fn filter(&self) -> u8 {
+        self.filter
+    }
+
+    /// Returns the filter match index (FMI) reported by [`Envelope::filter_match_index`].
+    ///
+    /// [`Envelope::filter_match_index`]: crate::Envelope::filter_match_index
+    #[inline]
+    pub fn filter_match_index(&self) -> u8 {
+        self.filter_match_index
+    }
+}
+
 /// Interface to the filter banks of a CAN peripheral.
 pub struct MasterFilters<'a, I: FilterOwner> {
     /// Number of assigned filter banks.
@@ -257,6 +725,23 @@ impl<I: FilterOwner> MasterFilters<'_, I> {
         self.bank_count
     }
 
+    /// Returns the FIFO assignment and configuration of a filter bank, or `None` if the bank is
+    /// disabled.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn bank_config(&self, index: u8) -> Option<(Fifo, BankConfig)> {
+        self.banks_imm().bank_config(index)
+    }
+
+    /// Returns an iterator over the enabled filter banks, yielding their index, FIFO assignment
+    /// and configuration.
+    pub fn active_banks(&self) -> impl Iterator<Item = (u8, Fifo, BankConfig)> + '_ {
+        let banks = self.banks_imm();
+        (banks.start_idx..banks.start_idx + banks.bank_count)
+            .filter_map(move |index| Some((index, banks.bank_config(index)?)))
+            .map(|(index, (fifo, config))| (index, fifo, config))
+    }
+
     /// Disables all enabled filter banks.
     ///
     /// This causes all incoming frames to be disposed.
@@ -295,6 +780,15 @@ impl<I: FilterOwner> MasterFilters<'_, I> {
         self.banks_imm().enable(index, fifo, config.into());
         self
     }
+
+    /// Disables all banks, and enables the banks of `plan`, starting at the first bank assigned
+    /// to this instance.
+    ///
+    /// Panics if the plan needs more banks than [`num_banks`](Self::num_banks) returns.
+    pub fn apply_plan(&mut self, plan: &FilterPlan) -> &mut Self {
+        self.banks_imm().apply_plan(plan);
+        self
+    }
 }
 
 impl<I: MasterInstance> MasterFilters<'_, I> {
@@ -357,6 +851,23 @@ impl<I: Instance> SlaveFilters<'_, I> {
         self.bank_count
     }
 
+    /// Returns the FIFO assignment and configuration of a filter bank, or `None` if the bank is
+    /// disabled.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn bank_config(&self, index: u8) -> Option<(Fifo, BankConfig)> {
+        self.banks_imm().bank_config(index)
+    }
+
+    /// Returns an iterator over the enabled filter banks, yielding their index, FIFO assignment
+    /// and configuration.
+    pub fn active_banks(&self) -> impl Iterator<Item = (u8, Fifo, BankConfig)> + '_ {
+        let banks = self.banks_imm();
+        (banks.start_idx..banks.start_idx + banks.bank_count)
+            .filter_map(move |index| Some((index, banks.bank_config(index)?)))
+            .map(|(index, (fifo, config))| (index, fifo, config))
+    }
+
     /// Disables all enabled filter banks.
     ///
     /// This causes all incoming frames to be disposed.
@@ -389,6 +900,104 @@ impl<I: Instance> SlaveFilters<'_, I> {
         self.banks_imm().enable(index, fifo, config.into());
         self
     }
+
+    /// Disables all banks, and enables the banks of `plan`, starting at the first bank assigned
+    /// to this instance.
+    ///
+    /// Panics if the plan needs more banks than [`num_banks`](Self::num_banks) returns.
+    pub fn apply_plan(&mut self, plan: &FilterPlan) -> &mut Self {
+        self.banks_imm().apply_plan(plan);
+        self
+    }
+}
+
+/// Interface to reconfigure individual filter banks while the other banks keep filtering.
+///
+/// Unlike [`MasterFilters`], this does not put the filters into initialization mode while it
+/// exists. Instead, a bank is deactivated while its identifiers are written, which the hardware
+/// allows at any time. [`disable_bank`](Self::disable_bank) and [`bank_config`](Self::bank_config)
+/// never affect other banks either.
+///
+/// Changing the mode (list or mask), scale (16 or 32 bits) or FIFO assignment of a bank briefly
+/// stops filtering on all banks: it requires initialization mode, which
+/// [`enable_bank`](Self::enable_bank) enters for a few register accesses only. Frames whose
+/// reception completes in that window are lost, so it can help to run it in a critical section.
+/// Keeping mode, scale and FIFO assignment of a bank avoids this.
+pub struct LiveFilters<'a, I: FilterOwner> {
+    start_idx: u8,
+    bank_count: u8,
+    _can: PhantomData<&'a mut I>,
+}
+
+impl<I: FilterOwner> LiveFilters<'_, I> {
+    pub(crate) unsafe fn new() -> Self {
+        let can = &*I::REGISTERS;
+        Self {
+            start_idx: 0,
+            bank_count: can.fmr.read().can2sb().bits(),
+            _can: PhantomData,
+        }
+    }
+
+    fn registers(&self) -> &RegisterBlock {
+        unsafe { &*I::REGISTERS }
+    }
+
+    fn banks_imm(&self) -> FilterBanks<'_> {
+        FilterBanks {
+            start_idx: self.start_idx,
+            bank_count: self.bank_count,
+            can: self.registers(),
+        }
+    }
+
+    /// Returns the number of filter banks accessible through this interface.
+    pub fn num_banks(&self) -> u8 {
+        self.bank_count
+    }
+
+    /// Returns the FIFO assignment and configuration of a filter bank, or `None` if the bank is
+    /// disabled.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn bank_config(&self, index: u8) -> Option<(Fifo, BankConfig)> {
+        self.banks_imm().bank_config(index)
+    }
+
+    /// Disables a filter bank.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn disable_bank(&mut self, index: u8) -> &mut Self {
+        self.banks_imm().disable(index);
+        self
+    }
+
+    /// Replaces the configuration of a filter bank and enables it, while the other banks keep
+    /// accepting frames.
+    ///
+    /// The bank itself does not accept frames while it is being reconfigured.
+    ///
+    /// If `index` is out of bounds, this will panic.
+    pub fn enable_bank(
+        &mut self,
+        index: u8,
+        fifo: Fifo,
+        config: impl Into<BankConfig>,
+    ) -> &mut Self {
+        self.banks_imm().enable_live(index, fifo, config.into());
+        self
+    }
+}
+
+impl<I: MasterInstance> LiveFilters<'_, I> {
+    /// Accesses the filters assigned to the slave peripheral.
+    pub fn slave_filters(&mut self) -> LiveFilters<'_, I> {
+        LiveFilters {
+            start_idx: self.bank_count,
+            bank_count: I::NUM_FILTER_BANKS - self.bank_count,
+            _can: PhantomData,
+        }
+    }
 }
 
 struct FilterBanks<'a> {
@@ -408,10 +1017,40 @@ impl FilterBanks<'_> {
         });
     }
 
+    fn apply_plan(&mut self, plan: &FilterPlan) {
+        assert!(plan.banks().len() <= usize::from(self.bank_count));
+
+        self.clear();
+        for (index, (fifo, config)) in (self.start_idx..).zip(plan.banks()) {
+            self.enable(index, *fifo, *config);
+        }
+    }
+
     fn assert_bank_index(&self, index: u8) {
         assert!((self.start_idx..self.start_idx + self.bank_count).contains(&index));
     }
 
+    fn bank_config(&self, index: u8) -> Option<(Fifo, BankConfig)> {
+        self.assert_bank_index(index);
+
+        let bit = 1 << index;
+        if self.can.fa1r.read().bits() & bit == 0 {
+            return None;
+        }
+
+        let list = self.can.fm1r.read().bits() & bit != 0;
+        let wide = self.can.fs1r.read().bits() & bit != 0;
+        let fifo = if self.can.ffa1r.read().bits() & bit == 0 {
+            Fifo::Fifo0
+        } else {
+            Fifo::Fifo1
+        };
+        let bank = &self.can.fb[usize::from(index)];
+        let config =
+            BankConfig::from_registers(list, wide, bank.fr1.read().bits(), bank.fr2.read().bits());
+        Some((fifo, config))
+    }
+
     fn disable(&mut self, index: u8) {
         self.assert_bank_index(index);
 
@@ -423,8 +1062,37 @@ impl FilterBanks<'_> {
     fn enable(&mut self, index: u8, fifo: Fifo, config: BankConfig) {
         self.assert_bank_index(index);
 
+        self.set_layout(index, fifo, &config);
+        self.write_filters(index, &config);
+        self.activate(index);
+    }
+
+    /// Like `enable`, but without requiring filter initialization mode.
+    fn enable_live(&mut self, index: u8, fifo: Fifo, config: BankConfig) {
+        self.assert_bank_index(index);
+
+        // The filter registers of an inactive bank can be written at any time.
+        self.disable(index);
+
+        let bit = 1 << index;
+        let layout_changed = (self.can.fm1r.read().bits() & bit != 0) != config.is_list()
+            || (self.can.fs1r.read().bits() & bit != 0) != config.is_32bit()
+            || (self.can.ffa1r.read().bits() & bit != 0) != (fifo == Fifo::Fifo1);
+        if layout_changed {
+            // Mode, scale and FIFO assignment can only be changed in initialization mode.
+            self.can.fmr.modify(|_, w| w.finit().set_bit());
+            self.set_layout(index, fifo, &config);
+            self.can.fmr.modify(|_, w| w.finit().clear_bit());
+        }
+
+        self.write_filters(index, &config);
+        self.activate(index);
+    }
+
+    /// Configures mode, scale and FIFO assignment of a bank.
+    fn set_layout(&mut self, index: u8, fifo: Fifo, config: &BankConfig) {
         // Configure mode.
-        let mode = matches!(config, BankConfig::List16(_) | BankConfig::List32(_));
+        let mode = config.is_list();
         self.can.fm1r.modify(|r, w| {
             let mut bits = r.bits();
             if mode {
@@ -436,7 +1104,7 @@ impl FilterBanks<'_> {
         });
 
         // Configure scale.
-        let scale = matches!(config, BankConfig::List32(_) | BankConfig::Mask32(_));
+        let scale = config.is_32bit();
         self.can.fs1r.modify(|r, w| {
             let mut bits = r.bits();
             if scale {
@@ -447,30 +1115,6 @@ impl FilterBanks<'_> {
             unsafe { w.bits(bits) }
         });
 
-        // Configure filter register.
-        let (fxr1, fxr2);
-        match config {
-            BankConfig::List16([a, b, c, d]) => {
-                fxr1 = (u32::from(b.0) << 16) | u32::from(a.0);
-                fxr2 = (u32::from(d.0) << 16) | u32::from(c.0);
-            }
-            BankConfig::List32([a, b]) => {
-                fxr1 = a.0;
-                fxr2 = b.0;
-            }
-            BankConfig::Mask16([a, b]) => {
-                fxr1 = (u32::from(a.mask) << 16) | u32::from(a.id);
-                fxr2 = (u32::from(b.mask) << 16) | u32::from(b.id);
-            }
-            BankConfig::Mask32(a) => {
-                fxr1 = a.id;
-                fxr2 = a.mask;
-            }
-        };
-        let bank = &self.can.fb[usize::from(index)];
-        bank.fr1.write(|w| unsafe { w.bits(fxr1) });
-        bank.fr2.write(|w| unsafe { w.bits(fxr2) });
-
         // Assign to the right FIFO
         self.can.ffa1r.modify(|r, w| unsafe {
             let mut bits = r.bits();
@@ -480,8 +1124,16 @@ impl FilterBanks<'_> {
             }
             w.bits(bits)
         });
+    }
+
+    fn write_filters(&mut self, index: u8, config: &BankConfig) {
+        let (fxr1, fxr2) = config.registers();
+        let bank = &self.can.fb[usize::from(index)];
+        bank.fr1.write(|w| unsafe { w.bits(fxr1) });
+        bank.fr2.write(|w| unsafe { w.bits(fxr2) });
+    }
 
-        // Set active.
+    fn activate(&mut self, index: u8) {
         self.can
             .fa1r
             .modify(|r, w| unsafe { w.bits(r.bits() | (1 << index)) })
@@ -496,7 +1148,19 @@ fn filter_bitmask(start_idx: u8, bank_count: u8) -> u32 {
 
 #[cfg(test)]
 mod tests {
+    extern crate std;
+
     use super::*;
+    use crate::sim::{Sim, SimCan3};
+    use crate::Can;
+    use std::vec::Vec;
+
+    fn loopback<I: Instance>(instance: I) -> Can<I> {
+        Can::builder(instance)
+            .set_loopback(true)
+            .set_silent(true)
+            .enable()
+    }
 
     #[test]
     fn test_filter_bitmask() {
@@ -507,4 +1171,568 @@ mod tests {
         assert_eq!(filter_bitmask(8, 1), 0x100);
         assert_eq!(filter_bitmask(8, 4), 0xf00);
     }
+
+    #[test]
+    fn mask_ranges() {
+        let std = |raw| StandardId::new(raw).unwrap();
+        let ext = |raw| ExtendedId::new(raw).unwrap();
+        let accepts = |masks: &[BankConfig], id: Id| {
+            let mut filters = FilterSet::new();
+            for (bank, &config) in masks.iter().enumerate() {
+                filters.enable_bank(bank as u8, Fifo::Fifo0, config);
+            }
+            filters.evaluate(&Frame::new_data(id, [])).is_some()
+                && filters.evaluate(&Frame::new_remote(id, 0)).is_some()
+        };
+
+        let masks = Mask32::std_id_range(std(0x0FE), std(0x203))
+            .map(BankConfig::from)
+            .collect::<Vec<_>>();
+        assert_eq!(masks.len(), 3);
+        for raw in 0x0F0..0x210 {
+            let expected = (0x0FE..=0x203).contains(&raw);
+            assert_eq!(accepts(&masks, std(raw).into()), expected, "{raw:#x}");
+            assert!(!accepts(&masks, ext(raw.into()).into()));
+        }
+
+        let masks = Mask16::std_id_range(std(0x0FE), std(0x203))
+            .map(|mask| BankConfig::from([mask, mask]))
+            .collect::<Vec<_>>();
+        assert_eq!(masks.len(), 3);
+        for raw in 0x0F0..0x210 {
+            let expected = (0x0FE..=0x203).contains(&raw);
+            assert_eq!(accepts(&masks, std(raw).into()), expected, "{raw:#x}");
+        }
+
+        let masks = Mask32::ext_id_range(ext(0x1234_5601), ext(0x1234_5700))
+            .map(BankConfig::from)
+            .collect::<Vec<_>>();
+        assert_eq!(masks.len(), 9);
+        for raw in 0x1234_5600..0x1234_5710 {
+            let expected = (0x1234_5601..=0x1234_5700).contains(&raw);
+            assert_eq!(accepts(&masks, ext(raw).into()), expected, "{raw:#x}");
+        }
+        assert_eq!(Mask32::ext_id_range(ext(2), ext(1)).count(), 0);
+        assert_eq!(
+            Mask32::ext_id_range(ExtendedId::ZERO, ExtendedId::MAX).collect::<Vec<_>>(),
+            [Mask32::frames_with_ext_id(
+                ExtendedId::ZERO,
+                ExtendedId::ZERO
+            )]
+        );
+    }
+
+    #[test]
+    fn mask_covering() {
+        let std = |raw| StandardId::new(raw).unwrap();
+        let ext = |raw| ExtendedId::new(raw).unwrap();
+
+        assert_eq!(Mask16::covering(&[]), None);
+        assert_eq!(Mask32::covering(&[]), None);
+
+        assert_eq!(
+            Mask16::covering(&[std(0x120), std(0x123), std(0x120)]),
+            Some((Mask16::frames_with_std_id(std(0x120), std(0x7FC)), 2))
+        );
+        assert_eq!(
+            Mask32::covering(&[std(0x120).into(), std(0x123).into()]),
+            Some((Mask32::frames_with_std_id(std(0x120), std(0x7FC)), 2))
+        );
+        assert_eq!(
+            Mask32::covering(&[ext(0x100).into(), ext(0x1100).into()]),
+            Some((Mask32::frames_with_ext_id(ext(0x100), ext(0x1FFF_EFFF)), 0))
+        );
+
+        // Mixing standard and extended IDs leaves the IDE bit unconstrained. The standard ID
+        // bits overlap the upper 11 bits of extended IDs.
+        let ids = [
+            std(0x7FF).into(),
+            std(0x7FE).into(),
+            ext(0x1FFC_0000).into(),
+            ext(0x1FFC_0001).into(),
+        ];
+        let (mask, extra) = Mask32::covering(&ids).unwrap();
+        assert_eq!(extra, 2);
+        let mut filters = FilterSet::new();
+        filters.enable_bank(0, Fifo::Fifo0, mask);
+        let accepted = |id: Id| filters.evaluate(&Frame::new_data(id, [])).is_some();
+        for id in ids {
+            assert!(accepted(id));
+        }
+        assert!(accepted(ext(0x1FF8_0001).into()));
+        assert!(!accepted(ext(0x1FF0_0000).into()));
+        assert!(!accepted(ext(0x1FFC_0002).into()));
+        assert!(!accepted(std(0x7FD).into()));
+    }
+
+    #[test]
+    fn ext_id_16bit() {
+        let ext = |raw| ExtendedId::new(raw).unwrap();
+        let mut filters = FilterSet::new();
+        filters
+            .enable_bank(
+                0,
+                Fifo::Fifo0,
+                [
+                    ListEntry16::data_frames_with_ext_id(ext(0x1234_5678)),
+                    ListEntry16::remote_frames_with_ext_id(ext(0x0ABC_0000)),
+                    ListEntry16::data_frames_with_id(StandardId::new(0x48D).unwrap()),
+                    ListEntry16::data_frames_with_id(StandardId::ZERO),
+                ],
+            )
+            .enable_bank(
+                1,
+                Fifo::Fifo1,
+                [
+                    Mask16::frames_with_ext_id(ext(0x1FF0_0000), ext(0x1FF0_7FFF)),
+                    Mask16::frames_with_ext_id(ext(0x0000_8000), ext(0x0000_8000)),
+                ],
+            );
+        let accepted = |frame: Frame| {
+            filters
+                .evaluate(&frame)
+                .map(|accepted| (accepted.fifo(), accepted.filter_match_index()))
+        };
+
+        // Only bits 28 to 15 are compared.
+        assert_eq!(
+            accepted(Frame::new_data(ext(0x1234_0000), [])),
+            Some((Fifo::Fifo0, 0))
+        );
+        assert_eq!(
+            accepted(Frame::new_data(ext(0x1234_7FFF), [])),
+            Some((Fifo::Fifo0, 0))
+        );
+        assert_eq!(
+            accepted(Frame::new_remote(ext(0x0ABC_1234), 0)),
+            Some((Fifo::Fifo0, 1))
+        );
+        // The IDE bit distinguishes standard IDs with the same STID bits.
+        assert_eq!(
+            accepted(Frame::new_data(StandardId::new(0x48D).unwrap(), [])),
+            Some((Fifo::Fifo0, 2))
+        );
+        assert_eq!(
+            accepted(Frame::new_data(ext(0x1234_8000), [])),
+            Some((Fifo::Fifo1, 1))
+        );
+        assert_eq!(
+            accepted(Frame::new_remote(ext(0x1FFF_0000), 0)),
+            Some((Fifo::Fifo1, 0))
+        );
+        assert_eq!(accepted(Frame::new_data(ext(0x0ABC_0000), [])), None);
+        assert_eq!(
+            accepted(Frame::new_data(StandardId::new(0x7FC).unwrap(), [])),
+            None
+        );
+    }
+
+    #[test]
+    fn filter_set_priority() {
+        let id = StandardId::new(42).unwrap();
+        let other = StandardId::new(1).unwrap();
+        let data_frames = *Mask32::frames_with_std_id(id, StandardId::MAX).data_frames_only();
+        let mut filters = FilterSet::new();
+        filters
+            .enable_bank(0, Fifo::Fifo1, data_frames)
+            .enable_bank(1, Fifo::Fifo0, data_frames)
+            .enable_bank(
+                2,
+                Fifo::Fifo0,
+                [
+                    ListEntry16::data_frames_with_id(other),
+                    ListEntry16::data_frames_with_id(id),
+                    ListEntry16::remote_frames_with_id(id),
+                    ListEntry16::data_frames_with_id(other),
+                ],
+            )
+            .enable_bank(3, Fifo::Fifo1, [Mask16::accept_all(), Mask16::accept_all()])
+            .enable_bank(4, Fifo::Fifo1, [ListEntry32::remote_frames_with_id(id); 2]);
+
+        // The lower bank wins among 32-bit masks.
+        let accepted = filters.evaluate(&Frame::new_data(id, [])).unwrap();
+        assert_eq!(
+            (
+                accepted.fifo(),
+                accepted.bank(),
+                accepted.filter_match_index()
+            ),
+            (Fifo::Fifo1, 0, 0)
+        );
+
+        // 32-bit lists win over 32-bit masks and all 16-bit filters.
+        let accepted = filters.evaluate(&Frame::new_remote(id, 0)).unwrap();
+        assert_eq!(
+            (accepted.fifo(), accepted.bank(), accepted.filter()),
+            (Fifo::Fifo1, 4, 0)
+        );
+        assert_eq!(accepted.filter_match_index(), 3);
+
+        // 16-bit lists win over 16-bit masks.
+        filters.disable_bank(4);
+        let accepted = filters.evaluate(&Frame::new_remote(id, 0)).unwrap();
+        assert_eq!(
+            (accepted.fifo(), accepted.bank(), accepted.filter()),
+            (Fifo::Fifo0, 2, 2)
+        );
+        assert_eq!(accepted.filter_match_index(), 3);
+
+        let accepted = filters
+            .evaluate(&Frame::new_data(ExtendedId::new(7).unwrap(), []))
+            .unwrap();
+        assert_eq!(
+            (accepted.fifo(), accepted.bank(), accepted.filter()),
+            (Fifo::Fifo1, 3, 0)
+        );
+        assert_eq!(accepted.filter_match_index(), 1);
+
+        filters.clear();
+        assert_eq!(filters.evaluate(&Frame::new_data(id, [])), None);
+    }
+
+    #[test]
+    fn filter_set_numbering() {
+        let mut filters = FilterSet::new_slave(14);
+        filters
+            .enable_bank(15, Fifo::Fifo1, Mask32::accept_all())
+            .enable_bank(16, Fifo::Fifo0, Mask32::accept_all())
+            .enable_bank(17, Fifo::Fifo1, Mask32::accept_all())
+            .disable_bank(15)
+            .disable_bank(16);
+
+        // Bank 14 was never configured, and counts as a 16-bit mask bank in FIFO 0.
+        assert_eq!(filters.filter_match_index(14, 1), 1);
+        assert_eq!(filters.filter_match_index(16, 0), 2);
+        assert_eq!(filters.filter_match_index(17, 0), 1);
+
+        let accepted = filters
+            .evaluate(&Frame::new_data(StandardId::new(0).unwrap(), []))
+            .unwrap();
+        assert_eq!(accepted.bank(), 17);
+        assert_eq!(accepted.filter_match_index(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn filter_set_master_bank() {
+        FilterSet::new_slave(14).enable_bank(13, Fifo::Fifo0, Mask32::accept_all());
+    }
+
+    #[test]
+    fn slave_filters() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = loopback(p.can1);
+        let mut can2 = loopback(p.can2);
+
+        can1.modify_filters()
+            .set_split(1)
+            .clear()
+            .enable_bank(
+                0,
+                Fifo::Fifo0,
+                Mask32::frames_with_std_id(StandardId::ZERO, StandardId::MAX),
+            )
+            .slave_filters()
+            .clear()
+            .enable_bank(1, Fifo::Fifo1, Mask32::accept_all());
+
+        let frame = Frame::new_data(StandardId::new(5).unwrap(), [5]);
+        can1.transmit(&frame).unwrap();
+        can2.transmit(&frame).unwrap();
+        sim.step();
+
+        assert!(can1.receive().is_err());
+        let envelope = can2.receive_with_meta().unwrap();
+        assert_eq!(*envelope.frame(), frame);
+        assert_eq!(envelope.fifo(), Fifo::Fifo1);
+        assert_eq!(envelope.filter_match_index(), 0);
+    }
+
+    #[test]
+    fn filter_priority() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can1);
+
+        let id = StandardId::new(42).unwrap();
+        let data_frames = *Mask32::frames_with_std_id(id, StandardId::MAX).data_frames_only();
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo1, data_frames)
+            .enable_bank(1, Fifo::Fifo0, data_frames)
+            .enable_bank(
+                2,
+                Fifo::Fifo0,
+                [
+                    ListEntry16::data_frames_with_id(StandardId::new(1).unwrap()),
+                    ListEntry16::data_frames_with_id(id),
+                    ListEntry16::remote_frames_with_id(id),
+                    ListEntry16::data_frames_with_id(StandardId::new(1).unwrap()),
+                ],
+            )
+            .enable_bank(3, Fifo::Fifo1, [Mask16::accept_all(), Mask16::accept_all()]);
+
+        // The lower bank wins among 32-bit filters.
+        let frame = Frame::new_data(id, [1, 2, 3]);
+        can.transmit(&frame).unwrap();
+        sim.step();
+        let envelope = can.receive_with_meta().unwrap();
+        assert_eq!(envelope.fifo(), Fifo::Fifo1);
+        assert_eq!(envelope.filter_match_index(), 0);
+
+        // 16-bit filters only match if no 32-bit filter does. Bank 2 provides FMI 1 to 4 of FIFO
+        // 0, and bank 3 provides FMI 1 and 2 of FIFO 1.
+        let frame = Frame::new_remote(id, 0);
+        can.transmit(&frame).unwrap();
+        sim.step();
+        let envelope = can.receive_with_meta().unwrap();
+        assert_eq!(envelope.fifo(), Fifo::Fifo0);
+        assert_eq!(envelope.filter_match_index(), 3);
+
+        let frame = Frame::new_data(ExtendedId::new(7).unwrap(), []);
+        can.transmit(&frame).unwrap();
+        sim.step();
+        let envelope = can.receive_with_meta().unwrap();
+        assert_eq!(envelope.fifo(), Fifo::Fifo1);
+        assert_eq!(envelope.filter_match_index(), 1);
+    }
+
+    /// Compares the filters of the simulated peripheral to the software model in [`FilterSet`],
+    /// using random configurations and frames.
+    #[test]
+    fn filter_set_model() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+
+        let mut state = 0x2545_F491_u32;
+        let mut rng = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        // Identifiers only differ in a few bits, so that filters match frequently.
+        let random_id = |rng: &mut dyn FnMut() -> u32| -> Id {
+            let bits = rng();
+            if bits & 1 == 0 {
+                StandardId::new((bits >> 1 & 0b11) as u16).unwrap().into()
+            } else {
+                ExtendedId::new((bits >> 1 & 0b11) << 18 | (bits >> 3 & 0b11) << 15 | bits >> 5 & 1)
+                    .unwrap()
+                    .into()
+            }
+        };
+        let std_id = |id: Id| match id {
+            Id::Standard(id) => id,
+            Id::Extended(id) => id.standard_id(),
+        };
+        let ext_id = |id: Id| match id {
+            Id::Standard(id) => ExtendedId::new(u32::from(id.as_raw()) << 18).unwrap(),
+            Id::Extended(id) => id,
+        };
+
+        for _ in 0..50 {
+            let mut model = FilterSet::new();
+            let mut filters = can.modify_filters();
+            filters.clear();
+            for bank in 0..6 {
+                let fifo = if rng() & 1 == 0 {
+                    Fifo::Fifo0
+                } else {
+                    Fifo::Fifo1
+                };
+                let config: BankConfig = match rng() % 4 {
+                    0 => {
+                        let id = random_id(&mut rng);
+                        let mask = ExtendedId::new(rng() & 0x1FFF_FFFF).unwrap();
+                        let mut mask = Mask32::frames_with_ext_id(ext_id(id), mask);
+                        match rng() % 3 {
+                            0 => mask.data_frames_only(),
+                            1 => mask.remote_frames_only(),
+                            _ => &mut mask,
+                        };
+                        mask.into()
+                    }
+                    1 => {
+                        let entry = |rng: &mut dyn FnMut() -> u32| {
+                            let id = random_id(rng);
+                            if rng() & 1 == 0 {
+                                ListEntry32::data_frames_with_id(id)
+                            } else {
+                                ListEntry32::remote_frames_with_id(id)
+                            }
+                        };
+                        [entry(&mut rng), entry(&mut rng)].into()
+                    }
+                    2 => {
+                        let mask = |rng: &mut dyn FnMut() -> u32| {
+                            let id = random_id(rng);
+                            if rng() & 1 == 0 {
+                                let mask = StandardId::new((rng() & 0x7FF) as u16).unwrap();
+                                Mask16::frames_with_std_id(std_id(id), mask)
+                            } else {
+                                let mask = ExtendedId::new(rng() & 0x1FFF_FFFF).unwrap();
+                                Mask16::frames_with_ext_id(ext_id(id), mask)
+                            }
+                        };
+                        [mask(&mut rng), mask(&mut rng)].into()
+                    }
+                    _ => {
+                        let entry = |rng: &mut dyn FnMut() -> u32| {
+                            let id = random_id(rng);
+                            match rng() % 4 {
+                                0 => ListEntry16::data_frames_with_id(std_id(id)),
+                                1 => ListEntry16::remote_frames_with_id(std_id(id)),
+                                2 => ListEntry16::data_frames_with_ext_id(ext_id(id)),
+                                _ => ListEntry16::remote_frames_with_ext_id(ext_id(id)),
+                            }
+                        };
+                        [
+                            entry(&mut rng),
+                            entry(&mut rng),
+                            entry(&mut rng),
+                            entry(&mut rng),
+                        ]
+                        .into()
+                    }
+                };
+                filters.enable_bank(bank, fifo, config);
+                model.enable_bank(bank, fifo, config);
+                // Disabled banks keep their configuration, which affects filter match indices.
+                if rng() % 4 == 0 {
+                    filters.disable_bank(bank);
+                    model.disable_bank(bank);
+                }
+            }
+            drop(filters);
+
+            for _ in 0..20 {
+                let id = random_id(&mut rng);
+                let frame = if rng() & 1 == 0 {
+                    Frame::new_data(id, [])
+                } else {
+                    Frame::new_remote(id, 0)
+                };
+                can.transmit(&frame).unwrap();
+                sim.step();
+
+                let received = can
+                    .receive_with_meta()
+                    .ok()
+                    .map(|envelope| (envelope.fifo(), envelope.filter_match_index()));
+                let expected = model
+                    .evaluate(&frame)
+                    .map(|accepted| (accepted.fifo(), accepted.filter_match_index()));
+                assert_eq!(received, expected, "{:?}", frame);
+            }
+        }
+    }
+
+    #[test]
+    fn filter_readback() {
+        let (_sim, p) = Sim::new();
+        let mut can1 = Can::builder(p.can1).leave_disabled();
+
+        let list16 = BankConfig::from([
+            ListEntry16::data_frames_with_id(StandardId::new(1).unwrap()),
+            ListEntry16::remote_frames_with_id(StandardId::new(2).unwrap()),
+            ListEntry16::data_frames_with_id(StandardId::new(3).unwrap()),
+            ListEntry16::data_frames_with_id(StandardId::MAX),
+        ]);
+        let list32 = BankConfig::from([
+            ListEntry32::data_frames_with_id(ExtendedId::MAX),
+            ListEntry32::remote_frames_with_id(StandardId::new(4).unwrap()),
+        ]);
+        let mask16 = BankConfig::from([
+            Mask16::accept_all(),
+            *Mask16::frames_with_std_id(StandardId::new(5).unwrap(), StandardId::MAX)
+                .remote_frames_only(),
+        ]);
+        let mask32 = BankConfig::from(Mask32::frames_with_ext_id(
+            ExtendedId::new(6).unwrap(),
+            ExtendedId::new(0xFF).unwrap(),
+        ));
+
+        let mut filters = can1.modify_filters();
+        filters
+            .clear()
+            .set_split(4)
+            .enable_bank(0, Fifo::Fifo1, list16)
+            .enable_bank(1, Fifo::Fifo0, list32)
+            .enable_bank(3, Fifo::Fifo1, mask16)
+            .disable_bank(0);
+        filters
+            .slave_filters()
+            .clear()
+            .enable_bank(20, Fifo::Fifo0, mask32);
+
+        assert_eq!(filters.bank_config(0), None);
+        assert_eq!(filters.bank_config(1), Some((Fifo::Fifo0, list32)));
+        assert_eq!(filters.bank_config(2), None);
+        let active = filters.active_banks().collect::<Vec<_>>();
+        assert_eq!(active, [(1, Fifo::Fifo0, list32), (3, Fifo::Fifo1, mask16)]);
+
+        let slave = filters.slave_filters();
+        assert_eq!(slave.bank_config(20), Some((Fifo::Fifo0, mask32)));
+        let active = slave.active_banks().collect::<Vec<_>>();
+        assert_eq!(active, [(20, Fifo::Fifo0, mask32)]);
+    }
+
+    #[test]
+    fn live_filters() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+
+        let std_id = StandardId::new(1).unwrap();
+        let ext_id = |id| ExtendedId::new(id).unwrap();
+        can.modify_filters()
+            .clear()
+            .enable_bank(
+                0,
+                Fifo::Fifo0,
+                Mask32::frames_with_std_id(std_id, StandardId::MAX),
+            )
+            .enable_bank(
+                1,
+                Fifo::Fifo1,
+                [
+                    ListEntry32::data_frames_with_id(ext_id(5)),
+                    ListEntry32::data_frames_with_id(ext_id(6)),
+                ],
+            );
+
+        let mut roundtrip = |can: &mut Can<SimCan3>, id: Id| {
+            can.transmit(&Frame::new_data(id, [])).unwrap();
+            sim.step();
+            can.receive_with_meta().ok().map(|envelope| envelope.fifo())
+        };
+
+        // Same mode, scale and FIFO: only the identifiers change.
+        let list = BankConfig::from([
+            ListEntry32::data_frames_with_id(ext_id(7)),
+            ListEntry32::data_frames_with_id(ext_id(8)),
+        ]);
+        can.live_filters().enable_bank(1, Fifo::Fifo1, list);
+        assert_eq!(can.live_filters().bank_config(1), Some((Fifo::Fifo1, list)));
+        assert_eq!(roundtrip(&mut can, ext_id(7).into()), Some(Fifo::Fifo1));
+        assert_eq!(roundtrip(&mut can, ext_id(5).into()), None);
+        assert_eq!(roundtrip(&mut can, std_id.into()), Some(Fifo::Fifo0));
+
+        // Moving the bank to the other FIFO goes through initialization mode.
+        can.live_filters().enable_bank(1, Fifo::Fifo0, list);
+        assert_eq!(can.live_filters().bank_config(1), Some((Fifo::Fifo0, list)));
+        assert_eq!(roundtrip(&mut can, ext_id(8).into()), Some(Fifo::Fifo0));
+
+        // Changing the layout goes through initialization mode.
+        let masks = BankConfig::from([Mask16::accept_all(); 2]);
+        can.live_filters().enable_bank(1, Fifo::Fifo0, masks);
+        assert_eq!(
+            can.live_filters().bank_config(1),
+            Some((Fifo::Fifo0, masks))
+        );
+        assert_eq!(roundtrip(&mut can, ext_id(5).into()), Some(Fifo::Fifo0));
+
+        can.live_filters().disable_bank(1);
+        assert_eq!(roundtrip(&mut can, ext_id(5).into()), None);
+        assert_eq!(roundtrip(&mut can, std_id.into()), Some(Fifo::Fifo0));
+    }
 }
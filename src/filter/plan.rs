@@ -0,0 +1,648 @@
+//! Automatic allocation of filter banks.
+
+use super::{BankConfig, ListEntry16, ListEntry32, Mask16, Mask32, MAX_FILTER_BANKS};
+use super::{F16_IDE, F16_RTR, F32_IDE, F32_RTR};
+use crate::{ExtendedId, Fifo, Id, StandardId};
+
+/// The frames accepted by an [`AcceptRule`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+enum FrameKind {
+    Data,
+    Remote,
+    Both,
+}
+
+/// A set of frames that should pass the filters, see [`FilterPlanner`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct AcceptRule {
+    extended: bool,
+    first: u32,
+    last: u32,
+    kind: FrameKind,
+}
+
+impl AcceptRule {
+    /// Accepts data and remote frames with the given ID.
+    pub fn id(id: impl Into<Id>) -> Self {
+        match id.into() {
+            Id::Standard(id) => Self::std_range(id, id),
+            Id::Extended(id) => Self::ext_range(id, id),
+        }
+    }
+
+    /// Accepts data and remote frames with standard IDs from `first` to `last`, inclusive.
+    ///
+    /// Panics if `first` is greater than `last`.
+    pub fn std_range(first: StandardId, last: StandardId) -> Self {
+        assert!(first.as_raw() <= last.as_raw());
+        Self {
+            extended: false,
+            first: first.as_raw().into(),
+            last: last.as_raw().into(),
+            kind: FrameKind::Both,
+        }
+    }
+
+    /// Accepts data and remote frames with extended IDs from `first` to `last`, inclusive.
+    ///
+    /// Panics if `first` is greater than `last`.
+    pub fn ext_range(first: ExtendedId, last: ExtendedId) -> Self {
+        assert!(first.as_raw() <= last.as_raw());
+        Self {
+            extended: true,
+            first: first.as_raw(),
+            last: last.as_raw(),
+            kind: FrameKind::Both,
+        }
+    }
+
+    /// Makes the rule accept data frames only.
+    #[must_use]
+    pub fn data_frames_only(mut self) -> Self {
+        self.kind = FrameKind::Data;
+        self
+    }
+
+    /// Makes the rule accept remote frames only.
+    #[must_use]
+    pub fn remote_frames_only(mut self) -> Self {
+        self.kind = FrameKind::Remote;
+        self
+    }
+
+    /// Returns the filters that accept exactly the frames of this rule.
+    fn filters(self, fifo: Fifo) -> impl Iterator<Item = Filter> {
+        let (rtr, rtr_mask) = match self.kind {
+            FrameKind::Data => (0, 1),
+            FrameKind::Remote => (1, 1),
+            FrameKind::Both => (0, 0),
+        };
+        let width = if self.extended { 29 } else { 11 };
+        let filter = move |id: u32, id_mask: u32, rtr: u32, rtr_mask: u32| {
+            let mut filter = Filter {
+                fifo,
+                extended: self.extended,
+                id: id << 1 | rtr,
+                mask: id_mask << 1 | rtr_mask,
+                covered: 0,
+            };
+            filter.covered = filter.size();
+            filter
+        };
+
+        RangeBlocks::new(self.first, self.last, width).flat_map(move |(id, id_mask)| {
+            // A single ID for both frame types fits into the same space as 2 list entries, which
+            // are easier to pack.
+            let split = rtr_mask == 0 && id_mask == (1 << width) - 1;
+            let first = if split {
+                filter(id, id_mask, 0, 1)
+            } else {
+                filter(id, id_mask, rtr, rtr_mask)
+            };
+            let second = if split {
+                Some(filter(id, id_mask, 1, 1))
+            } else {
+                None
+            };
+            core::iter::once(first).chain(second)
+        })
+    }
+}
+
+/// Splits an inclusive range of identifiers into the smallest number of blocks that can each be
+/// matched by a single mask, returned as `(id, mask)` pairs.
+pub(super) struct RangeBlocks {
+    next: u64,
+    end: u64,
+    width: u32,
+}
+
+impl RangeBlocks {
+    pub(super) fn new(first: u32, last: u32, width: u32) -> Self {
+        Self {
+            next: first.into(),
+            end: u64::from(last) + 1,
+            width,
+        }
+    }
+}
+
+impl Iterator for RangeBlocks {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<(u32, u32)> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        // The largest aligned block starting at `next` that does not extend past `end`.
+        let mut size = 1u64 << self.next.trailing_zeros().min(self.width);
+        while self.next + size > self.end {
+            size >>= 1;
+        }
+        let mask = ((1u64 << self.width) - size) as u32;
+        let id = self.next as u32;
+        self.next += size;
+        Some((id, mask))
+    }
+}
+
+/// Kinds of filters, by the space they occupy in a filter bank.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    /// A quarter bank, in a 16-bit list bank.
+    StdList,
+    /// Half a bank, in a 32-bit list bank.
+    ExtList,
+    /// Half a bank, in a 16-bit mask bank.
+    StdMask,
+    /// A whole 32-bit mask bank.
+    ExtMask,
+}
+
+/// A single identifier mask, not yet assigned to a filter bank.
+#[derive(Debug, Copy, Clone)]
+struct Filter {
+    fifo: Fifo,
+    extended: bool,
+    /// Identifier, followed by the RTR bit.
+    id: u32,
+    mask: u32,
+    /// Number of requested frame identifiers this filter accepts.
+    covered: u64,
+}
+
+impl Filter {
+    const EMPTY: Self = Self {
+        fifo: Fifo::Fifo0,
+        extended: false,
+        id: 0,
+        mask: 0,
+        covered: 0,
+    };
+
+    fn width(&self) -> u32 {
+        if self.extended {
+            30
+        } else {
+            12
+        }
+    }
+
+    /// Returns the number of frame identifiers (including the RTR bit) the filter accepts.
+    fn size(&self) -> u64 {
+        1 << (self.width() - self.mask.count_ones())
+    }
+
+    fn kind(&self) -> Kind {
+        let exact = self.mask == (1 << self.width()) - 1;
+        match (self.extended, exact) {
+            (false, true) => Kind::StdList,
+            (true, true) => Kind::ExtList,
+            (false, false) => Kind::StdMask,
+            (true, false) => Kind::ExtMask,
+        }
+    }
+
+    /// Returns the tightest filter accepting the frames of both `self` and `other`.
+    fn merge(&self, other: &Filter) -> Filter {
+        let mask = self.mask & other.mask & !(self.id ^ other.id);
+        Filter {
+            id: self.id & mask,
+            mask,
+            covered: self.covered.saturating_add(other.covered),
+            ..*self
+        }
+    }
+
+    /// Returns `true` if `self` accepts all frames `other` accepts.
+    fn covers(&self, other: &Filter) -> bool {
+        self.fifo == other.fifo
+            && self.extended == other.extended
+            && other.mask & self.mask == self.mask
+            && (self.id ^ other.id) & self.mask == 0
+    }
+
+    fn list_entry16(&self) -> ListEntry16 {
+        ListEntry16(((self.id >> 1) << 5 | (self.id & 1) << 4) as u16)
+    }
+
+    fn list_entry32(&self) -> ListEntry32 {
+        if self.extended {
+            ListEntry32((self.id >> 1) << 3 | F32_IDE | (self.id & 1) << 1)
+        } else {
+            ListEntry32((self.id >> 1) << 21 | (self.id & 1) << 1)
+        }
+    }
+
+    fn mask16(&self) -> Mask16 {
+        let rtr = if self.mask & 1 != 0 { F16_RTR } else { 0 };
+        Mask16 {
+            id: self.list_entry16().0,
+            mask: ((self.mask >> 1) << 5) as u16 | rtr | F16_IDE,
+        }
+    }
+
+    fn mask32(&self) -> Mask32 {
+        let rtr = if self.mask & 1 != 0 { F32_RTR } else { 0 };
+        Mask32 {
+            id: self.list_entry32().0,
+            mask: (self.mask >> 1) << 3 | rtr | F32_IDE,
+        }
+    }
+}
+
+/// Number of filters of each [`Kind`], in the order of its variants.
+type Counts = [usize; 4];
+
+/// Returns the number of filter banks needed for filters of a single FIFO.
+fn banks_needed(counts: &Counts) -> usize {
+    let [std_lists, ext_lists, std_masks, ext_masks] = *counts;
+    // Odd numbers of 32-bit list entries and 16-bit masks leave a half bank free, which can take
+    // a standard ID.
+    let free_slots = ext_lists % 2 + std_masks % 2;
+    let std_lists = std_lists.saturating_sub(free_slots);
+    ext_masks + ext_lists.div_ceil(2) + std_masks.div_ceil(2) + std_lists.div_ceil(4)
+}
+
+/// Error returned by [`FilterPlanner`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum PlanError {
+    /// The planner cannot hold the filters needed for an [`AcceptRule`].
+    CapacityExceeded,
+    /// The rules cannot be satisfied with the available filter banks, even when merging filters.
+    ///
+    /// This happens when there are fewer banks than FIFOs and frame formats (standard or
+    /// extended) used by the rules.
+    NotEnoughBanks,
+}
+
+/// Plans the filter bank configuration for a set of [`AcceptRule`]s.
+///
+/// The planner breaks every rule down into identifier list entries and masks, and packs them into
+/// as few filter banks as possible. If that needs more banks than available, it merges similar
+/// filters, which makes the filters accept frames that were not requested. The resulting
+/// [`FilterPlan`] reports how many.
+///
+/// The planner does not allocate memory. `N` is the maximum number of list entries and masks it
+/// can hold: a rule for a single ID for both data and remote frames takes 2, a single frame type
+/// takes 1, and a range takes 1 or 2 per power of two it spans.
+///
+/// # Example
+///
+/// ```
+/// use bxcan::filter::{AcceptRule, FilterPlanner};
+/// use bxcan::{Fifo, StandardId};
+///
+/// let mut planner = FilterPlanner::<16>::new();
+/// planner
+///     .accept(Fifo::Fifo0, AcceptRule::id(StandardId::new(0x100).unwrap()).data_frames_only())?
+///     .accept(
+///         Fifo::Fifo1,
+///         AcceptRule::std_range(StandardId::new(0x200).unwrap(), StandardId::new(0x27F).unwrap()),
+///     )?;
+///
+/// let plan = planner.plan(14)?;
+/// assert_eq!(plan.banks().len(), 2);
+/// assert_eq!(plan.over_acceptance(), 0);
+/// # Ok::<(), bxcan::filter::PlanError>(())
+/// ```
+///
+/// The plan can then be applied with [`MasterFilters::apply_plan`] or
+/// [`SlaveFilters::apply_plan`].
+///
+/// [`MasterFilters::apply_plan`]: super::MasterFilters::apply_plan
+/// [`SlaveFilters::apply_plan`]: super::SlaveFilters::apply_plan
+#[derive(Debug, Clone)]
+pub struct FilterPlanner<const N: usize> {
+    filters: [Filter; N],
+    len: usize,
+}
+
+impl<const N: usize> FilterPlanner<N> {
+    /// Creates a planner without any rules.
+    pub const fn new() -> Self {
+        Self {
+            filters: [Filter::EMPTY; N],
+            len: 0,
+        }
+    }
+
+    /// Adds a rule for frames to pass into `fifo`.
+    ///
+    /// Returns [`PlanError::CapacityExceeded`] without adding the rule if the planner is full.
+    pub fn accept(&mut self, fifo: Fifo, rule: AcceptRule) -> Result<&mut Self, PlanError> {
+        if self.len + rule.filters(fifo).count() > N {
+            return Err(PlanError::CapacityExceeded);
+        }
+
+        for filter in rule.filters(fifo) {
+            self.filters[self.len] = filter;
+            self.len += 1;
+        }
+        Ok(self)
+    }
+
+    /// Plans a configuration that fits into `num_banks` filter banks.
+    ///
+    /// Pass [`MasterFilters::num_banks`] or [`SlaveFilters::num_banks`] of the filters the plan is
+    /// applied to.
+    ///
+    /// [`MasterFilters::num_banks`]: super::MasterFilters::num_banks
+    /// [`SlaveFilters::num_banks`]: super::SlaveFilters::num_banks
+    pub fn plan(&self, num_banks: u8) -> Result<FilterPlan, PlanError> {
+        let num_banks = usize::from(num_banks.min(MAX_FILTER_BANKS));
+        let mut filters = self.filters;
+        let mut len = self.len;
+
+        loop {
+            let mut counts = [[0; 4]; 2];
+            for filter in &filters[..len] {
+                counts[filter.fifo as usize][filter.kind() as usize] += 1;
+            }
+            let banks = banks_needed(&counts[0]) + banks_needed(&counts[1]);
+            if banks <= num_banks {
+                break;
+            }
+
+            // Merge the pair of filters that saves the most banks while accepting the fewest
+            // additional frames. The merged filter replaces all filters it covers.
+            let mut best: Option<((usize, u64), usize, usize)> = None;
+            for i in 0..len {
+                for j in i + 1..len {
+                    let (a, b) = (&filters[i], &filters[j]);
+                    if a.fifo != b.fifo || a.extended != b.extended {
+                        continue;
+                    }
+
+                    let merged = a.merge(b);
+                    let mut counts = counts;
+                    let mut replaced = 0u64;
+                    for filter in filters[..len].iter().filter(|f| merged.covers(f)) {
+                        counts[filter.fifo as usize][filter.kind() as usize] -= 1;
+                        replaced += filter.size();
+                    }
+                    counts[merged.fifo as usize][merged.kind() as usize] += 1;
+                    let banks = banks_needed(&counts[0]) + banks_needed(&counts[1]);
+                    let cost = merged.size().saturating_sub(replaced);
+
+                    // Saving more banks than needed does not help.
+                    let key = (banks.max(num_banks), cost);
+                    if !matches!(best, Some((best_key, _, _)) if best_key <= key) {
+                        best = Some((key, i, j));
+                    }
+                }
+            }
+
+            let (_, i, j) = best.ok_or(PlanError::NotEnoughBanks)?;
+            let mut merged = filters[i].merge(&filters[j]);
+            merged.covered = 0;
+            let mut kept = 0;
+            for k in 0..len {
+                if merged.covers(&filters[k]) {
+                    merged.covered = merged.covered.saturating_add(filters[k].covered);
+                } else {
+                    filters[kept] = filters[k];
+                    kept += 1;
+                }
+            }
+            filters[kept] = merged;
+            len = kept + 1;
+        }
+
+        Ok(FilterPlan::assemble(&filters[..len]))
+    }
+}
+
+impl<const N: usize> Default for FilterPlanner<N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A filter bank configuration computed by [`FilterPlanner::plan`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct FilterPlan {
+    banks: [(Fifo, BankConfig); MAX_FILTER_BANKS as usize],
+    len: u8,
+    over_acceptance: u64,
+}
+
+impl FilterPlan {
+    fn assemble(filters: &[Filter]) -> Self {
+        let mut plan = FilterPlan {
+            banks: [(Fifo::Fifo0, BankConfig::Mask32(Mask32::accept_all()));
+                MAX_FILTER_BANKS as usize],
+            len: 0,
+            over_acceptance: 0,
+        };
+        let mut push = |fifo: Fifo, config: BankConfig| {
+            plan.banks[usize::from(plan.len)] = (fifo, config);
+            plan.len += 1;
+        };
+
+        for fifo in [Fifo::Fifo0, Fifo::Fifo1] {
+            let of_kind = move |kind| {
+                filters
+                    .iter()
+                    .filter(move |f| f.fifo == fifo && f.kind() == kind)
+            };
+            let mut std_lists = of_kind(Kind::StdList);
+
+            for filter in of_kind(Kind::ExtMask) {
+                push(fifo, filter.mask32().into());
+            }
+
+            // Unused slots are filled with standard IDs or repeat the first entry.
+            let mut ext_lists = of_kind(Kind::ExtList);
+            while let Some(a) = ext_lists.next() {
+                let b = ext_lists.next().or_else(|| std_lists.next()).unwrap_or(a);
+                push(fifo, [a.list_entry32(), b.list_entry32()].into());
+            }
+
+            let mut std_masks = of_kind(Kind::StdMask);
+            while let Some(a) = std_masks.next() {
+                let b = std_masks.next().or_else(|| std_lists.next()).unwrap_or(a);
+                push(fifo, [a.mask16(), b.mask16()].into());
+            }
+
+            while let Some(a) = std_lists.next() {
+                let mut entries = [a.list_entry16(); 4];
+                for entry in &mut entries[1..] {
+                    if let Some(filter) = std_lists.next() {
+                        *entry = filter.list_entry16();
+                    }
+                }
+                push(fifo, entries.into());
+            }
+        }
+
+        plan.over_acceptance = filters
+            .iter()
+            .map(|f| f.size().saturating_sub(f.covered))
+            .sum();
+        plan
+    }
+
+    /// Returns the planned filter banks, in the order they are assigned to the peripheral.
+    pub fn banks(&self) -> &[(Fifo, BankConfig)] {
+        &self.banks[..usize::from(self.len)]
+    }
+
+    /// Returns the number of frame identifiers that pass the filters although no rule asked for
+    /// them.
+    ///
+    /// Data and remote frames with the same ID count separately. This is 0 if no filters had to
+    /// be merged, and assumes that the rules do not overlap.
+    pub fn over_acceptance(&self) -> u64 {
+        self.over_acceptance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::filter::FilterSet;
+    use crate::Frame;
+    use std::vec::Vec;
+
+    fn std(id: u16) -> StandardId {
+        StandardId::new(id).unwrap()
+    }
+
+    fn ext(id: u32) -> ExtendedId {
+        ExtendedId::new(id).unwrap()
+    }
+
+    fn accepted(plan: &FilterPlan, frame: &Frame) -> Option<Fifo> {
+        let mut filters = FilterSet::new();
+        filters.apply_plan(plan);
+        filters.evaluate(frame).map(|accepted| accepted.fifo())
+    }
+
+    #[test]
+    fn range_blocks() {
+        let blocks = |first, last, width| RangeBlocks::new(first, last, width).collect::<Vec<_>>();
+        assert_eq!(blocks(0, 0x7FF, 11), [(0, 0)]);
+        assert_eq!(blocks(5, 5, 11), [(5, 0x7FF)]);
+        assert_eq!(
+            blocks(3, 12, 11),
+            [(3, 0x7FF), (4, 0x7FC), (8, 0x7FC), (12, 0x7FF)]
+        );
+        assert_eq!(blocks(0x100, 0x1FF, 29), [(0x100, 0x1FFF_FF00)]);
+        assert_eq!(blocks(0, 0x1FFF_FFFE, 29).len(), 29,);
+    }
+
+    #[test]
+    fn optimal_packing() {
+        let mut planner = FilterPlanner::<32>::new();
+        // 5 standard data frame IDs, 1 remote frame ID.
+        for id in 1..=5 {
+            planner
+                .accept(Fifo::Fifo0, AcceptRule::id(std(id)).data_frames_only())
+                .unwrap();
+        }
+        planner
+            .accept(Fifo::Fifo0, AcceptRule::id(std(6)).remote_frames_only())
+            .unwrap();
+        // An extended ID, for both data and remote frames.
+        planner
+            .accept(Fifo::Fifo0, AcceptRule::id(ext(0x1234)))
+            .unwrap();
+        // A standard range taking a single 16-bit mask.
+        planner
+            .accept(Fifo::Fifo1, AcceptRule::std_range(std(0x100), std(0x1FF)))
+            .unwrap();
+
+        // The standard IDs need 1.5 banks, the extended one 1 bank, the range half a bank.
+        let plan = planner.plan(28).unwrap();
+        assert_eq!(plan.banks().len(), 4);
+        assert_eq!(plan.over_acceptance(), 0);
+
+        for id in 1..=5 {
+            assert_eq!(
+                accepted(&plan, &Frame::new_data(std(id), [])),
+                Some(Fifo::Fifo0)
+            );
+            assert_eq!(accepted(&plan, &Frame::new_remote(std(id), 0)), None);
+        }
+        assert_eq!(
+            accepted(&plan, &Frame::new_remote(std(6), 0)),
+            Some(Fifo::Fifo0)
+        );
+        assert_eq!(accepted(&plan, &Frame::new_data(std(6), [])), None);
+        assert_eq!(accepted(&plan, &Frame::new_data(std(7), [])), None);
+        assert_eq!(
+            accepted(&plan, &Frame::new_data(ext(0x1234), [])),
+            Some(Fifo::Fifo0)
+        );
+        assert_eq!(
+            accepted(&plan, &Frame::new_remote(ext(0x1234), 0)),
+            Some(Fifo::Fifo0)
+        );
+        assert_eq!(accepted(&plan, &Frame::new_data(ext(0x1235), [])), None);
+        assert_eq!(
+            accepted(&plan, &Frame::new_data(std(0x1AB), [])),
+            Some(Fifo::Fifo1)
+        );
+        assert_eq!(
+            accepted(&plan, &Frame::new_remote(std(0x100), 0)),
+            Some(Fifo::Fifo1)
+        );
+        assert_eq!(accepted(&plan, &Frame::new_data(std(0x200), [])), None);
+    }
+
+    #[test]
+    fn merging() {
+        let mut planner = FilterPlanner::<16>::new();
+        for id in [0x10, 0x11, 0x12, 0x13, 0x80] {
+            planner
+                .accept(Fifo::Fifo1, AcceptRule::id(ext(id)).data_frames_only())
+                .unwrap();
+        }
+        assert_eq!(planner.plan(3).unwrap().over_acceptance(), 0);
+
+        // 0x10 to 0x13 merge into a single mask without extra frames.
+        let plan = planner.plan(2).unwrap();
+        assert_eq!(plan.banks().len(), 2);
+        assert_eq!(plan.over_acceptance(), 0);
+
+        let plan = planner.plan(1).unwrap();
+        assert_eq!(plan.banks().len(), 1);
+        // The mask ignores bits 0, 1, 4 and 7 of the ID.
+        assert_eq!(plan.over_acceptance(), 16 - 5);
+        assert_eq!(
+            accepted(&plan, &Frame::new_data(ext(0x91), [])),
+            Some(Fifo::Fifo1)
+        );
+        assert_eq!(accepted(&plan, &Frame::new_remote(ext(0x91), 0)), None);
+        assert_eq!(accepted(&plan, &Frame::new_data(ext(0x42), [])), None);
+        assert_eq!(accepted(&plan, &Frame::new_data(ext(0x100), [])), None);
+
+        assert_eq!(planner.plan(0).unwrap_err(), PlanError::NotEnoughBanks);
+    }
+
+    #[test]
+    fn capacity() {
+        let mut planner = FilterPlanner::<3>::new();
+        planner.accept(Fifo::Fifo0, AcceptRule::id(std(1))).unwrap();
+        // A range from 1 to 6 needs 1, 2 to 3, 4 to 5 and 6.
+        assert_eq!(
+            planner
+                .accept(Fifo::Fifo0, AcceptRule::std_range(std(1), std(6)))
+                .unwrap_err(),
+            PlanError::CapacityExceeded
+        );
+        planner
+            .accept(Fifo::Fifo0, AcceptRule::id(std(2)).data_frames_only())
+            .unwrap();
+        assert_eq!(planner.plan(1).unwrap().banks().len(), 1);
+    }
+}
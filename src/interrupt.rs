@@ -27,7 +27,8 @@ pub enum Interrupt {
     /// transmission was aborted successfully.
     ///
     /// The interrupt handler must clear the interrupt condition by calling
-    /// [`Can::clear_request_completed_flag`] or [`Can::clear_tx_interrupt`].
+    /// [`Can::clear_request_completed_flag`], [`Can::clear_tx_interrupt`], or
+    /// [`Can::take_completions`].
     TransmitMailboxEmpty = 1 << 0,
 
     /// Fires the **RX FIFO 0** interrupt when FIFO 0 holds a message.
@@ -11,7 +11,8 @@
 //! - Handles standard and extended frames, and data and remote frames.
 //! - Support for interrupts emitted by the bxCAN peripheral.
 //! - Transmission respects CAN IDs and protects against priority inversion (a lower-priority frame
-//!   may be dequeued when enqueueing a higher-priority one).
+//!   may be dequeued when enqueueing a higher-priority one). Alternatively, frames can be
+//!   transmitted in FIFO order.
 //! - Implements the [`embedded-hal`] traits for interoperability.
 //! - Support for both RX FIFOs (as [`Rx0`] and [`Rx1`]).
 //!
@@ -20,6 +21,8 @@
 //! | Feature | Description |
 //! |---------|-------------|
 //! | `unstable-defmt` | Implements [`defmt`]'s `Format` trait for the types in this crate.[^1] |
+//! | `async` | Enables the interrupt-driven `async` API in the [`asynch`] module. |
+//! | `sim` | Enables the simulated peripherals in the [`sim`] module. Requires `std`. |
 //!
 //! [^1]: The specific version of defmt is unspecified and may be updated in a patch release.
 //!
@@ -32,22 +35,29 @@
 #![no_std]
 #![allow(clippy::unnecessary_operation)] // lint is bugged
 
+#[cfg(any(test, feature = "async"))]
+pub mod asynch;
+mod bit_timing;
 mod embedded_hal;
 pub mod filter;
 mod frame;
 mod id;
 mod interrupt;
 
-#[allow(clippy::all)] // generated code
+#[allow(unknown_lints, clippy::all, mismatched_lifetime_syntaxes)] // generated code
 mod pac;
+pub mod queue;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
 
 pub use id::{ExtendedId, Id, StandardId};
 
+pub use crate::bit_timing::{BitTiming, BitTimingError, BitTimingMatch};
 pub use crate::frame::{Data, Frame, FramePriority};
 pub use crate::interrupt::{Interrupt, Interrupts};
 pub use crate::pac::can::RegisterBlock;
 
-use crate::filter::MasterFilters;
+use crate::filter::{LiveFilters, MasterFilters};
 use core::cmp::{Ord, Ordering};
 use core::convert::{Infallible, TryInto};
 use core::marker::PhantomData;
@@ -141,7 +151,7 @@ impl ErrorStatus {
     /// The transmit error counter.
     #[inline]
     pub fn transmit_counter(&self) -> u8 {
-        self.recv_count
+        self.txmt_count
     }
 
     /// The last error code.
@@ -179,7 +189,63 @@ impl ErrorStatus {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
 pub struct OverrunError {
-    _priv: (),
+    fifo: Fifo,
+    policy: OverrunPolicy,
+}
+
+impl OverrunError {
+    /// Returns the receive FIFO that overran.
+    #[inline]
+    pub fn fifo(&self) -> Fifo {
+        self.fifo
+    }
+
+    /// Returns the overrun policy that was active when the error was reported.
+    ///
+    /// This determines which frame has been lost.
+    #[inline]
+    pub fn policy(&self) -> OverrunPolicy {
+        self.policy
+    }
+}
+
+/// Error returned by the blocking [`embedded_can::blocking::Can`] implementation of [`Can`].
+///
+/// [`embedded_can::blocking::Can`]: https://docs.rs/embedded-can/0.4/embedded_can/blocking/trait.Can.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum CanError {
+    /// An incoming message has been lost due to buffer overrun.
+    Overrun(OverrunError),
+    /// The peripheral is in bus-off state and can neither transmit nor receive frames.
+    ///
+    /// Depending on the configured [`BusOffRecovery`], the peripheral recovers automatically or
+    /// after calling [`Can::recover_from_bus_off`].
+    BusOff,
+}
+
+impl From<OverrunError> for CanError {
+    #[inline]
+    fn from(e: OverrunError) -> Self {
+        CanError::Overrun(e)
+    }
+}
+
+/// Determines which frame is lost when a frame arrives while its receive FIFO is full.
+///
+/// Configured with [`CanBuilder::set_rx_fifo_locked`] or [`CanConfig::set_rx_fifo_locked`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum OverrunPolicy {
+    /// The FIFO is not locked: the incoming frame overwrites the most recently received frame in
+    /// the FIFO.
+    ///
+    /// The two oldest frames are kept, followed by the newest one.
+    Overwrite,
+    /// The FIFO is locked: the incoming frame is discarded.
+    ///
+    /// The three oldest frames are kept.
+    Discard,
 }
 
 /// Identifier of a CAN message.
@@ -307,8 +373,10 @@ pub struct CanConfig<'a, I: Instance> {
 impl<I: Instance> CanConfig<'_, I> {
     /// Configures the bit timings.
     ///
-    /// You can use <http://www.bittiming.can-wiki.info/> to calculate the `btr` parameter. Enter
-    /// parameters as follows:
+    /// The `btr` parameter can be computed with [`BitTiming::calculate`] and [`BitTiming::btr`].
+    ///
+    /// Alternatively, you can use <http://www.bittiming.can-wiki.info/> to calculate the `btr`
+    /// parameter. Enter parameters as follows:
     ///
     /// - *Clock Rate*: The input clock speed to the CAN peripheral (*not* the CPU clock speed).
     ///   This is the clock rate of the peripheral bus the CAN peripheral is attached to (eg. APB1).
@@ -349,6 +417,74 @@ impl<I: Instance> CanConfig<'_, I> {
         self
     }
 
+    /// Configures how the peripheral recovers from the bus-off state.
+    ///
+    /// With [`BusOffRecovery::Automatic`], the peripheral rejoins the bus on its own once the
+    /// recovery sequence required by the CAN specification has been monitored on the bus. With
+    /// [`BusOffRecovery::Manual`], it stays in bus-off until recovery is requested with
+    /// [`Can::recover_from_bus_off`].
+    ///
+    /// [`BusOffRecovery::Automatic`] is the default.
+    pub fn set_bus_off_recovery(self, recovery: BusOffRecovery) -> Self {
+        let can = self.can.registers();
+        can.mcr
+            .modify(|_, w| w.abom().bit(recovery == BusOffRecovery::Automatic));
+        self
+    }
+
+    /// Enables or disables debug freeze.
+    ///
+    /// If this is enabled, reception and transmission are stopped while the CPU is halted by a
+    /// debugger (eg. at a breakpoint). The peripheral resumes operation once the CPU continues.
+    /// If it is disabled, the peripheral keeps communicating while the CPU is halted.
+    ///
+    /// Debug freeze is enabled after reset. Use [`Can::debug_freeze`] to read the current setting.
+    pub fn set_debug_freeze(self, enabled: bool) -> Self {
+        let can = self.can.registers();
+        can.mcr.modify(|_, w| w.dbf().bit(enabled));
+        self
+    }
+
+    /// Enables or disables the receive FIFO lock.
+    ///
+    /// This determines which frame is lost when a frame arrives while its receive FIFO is full
+    /// (see [`OverrunPolicy`]):
+    ///
+    /// - If the FIFO is locked, the incoming frame is discarded ([`OverrunPolicy::Discard`]).
+    /// - If the FIFO is not locked, the incoming frame overwrites the most recently received frame
+    ///   in the FIFO ([`OverrunPolicy::Overwrite`]).
+    ///
+    /// The setting applies to both FIFOs. The FIFOs are not locked by default.
+    pub fn set_rx_fifo_locked(self, enabled: bool) -> Self {
+        let can = self.can.registers();
+        can.mcr.modify(|_, w| w.rflm().bit(enabled));
+        self
+    }
+
+    /// Configures the order in which pending frames are transmitted.
+    ///
+    /// By default, frames are transmitted in order of their priority ([`TransmitOrder::Priority`]).
+    pub fn set_transmit_order(self, order: TransmitOrder) -> Self {
+        let can = self.can.registers();
+        can.mcr
+            .modify(|_, w| w.txfp().bit(order == TransmitOrder::Fifo));
+        self
+    }
+
+    /// Enables or disables Time Triggered Communication Mode (TTCM).
+    ///
+    /// In this mode, the peripheral's internal 16-bit timer is running, which counts CAN bit
+    /// times. It is used to timestamp received frames (see [`Envelope::timestamp`]) and transmitted
+    /// frames (see [`Tx::transmit_timestamp`]), and can be inserted into outgoing frames with
+    /// [`Tx::transmit_with_timestamp`].
+    ///
+    /// Time Triggered Communication Mode is disabled by default.
+    pub fn set_time_triggered_mode(self, enabled: bool) -> Self {
+        let can = self.can.registers();
+        can.mcr.modify(|_, w| w.ttcm().bit(enabled));
+        self
+    }
+
     /// Leaves initialization mode and enables the peripheral.
     ///
     /// To sync with the CAN bus, this will block until 11 consecutive recessive bits are detected
@@ -406,8 +542,10 @@ pub struct CanBuilder<I: Instance> {
 impl<I: Instance> CanBuilder<I> {
     /// Configures the bit timings.
     ///
-    /// You can use <http://www.bittiming.can-wiki.info/> to calculate the `btr` parameter. Enter
-    /// parameters as follows:
+    /// The `btr` parameter can be computed with [`BitTiming::calculate`] and [`BitTiming::btr`].
+    ///
+    /// Alternatively, you can use <http://www.bittiming.can-wiki.info/> to calculate the `btr`
+    /// parameter. Enter parameters as follows:
     ///
     /// - *Clock Rate*: The input clock speed to the CAN peripheral (*not* the CPU clock speed).
     ///   This is the clock rate of the peripheral bus the CAN peripheral is attached to (eg. APB1).
@@ -448,6 +586,74 @@ impl<I: Instance> CanBuilder<I> {
         self
     }
 
+    /// Configures how the peripheral recovers from the bus-off state.
+    ///
+    /// With [`BusOffRecovery::Automatic`], the peripheral rejoins the bus on its own once the
+    /// recovery sequence required by the CAN specification has been monitored on the bus. With
+    /// [`BusOffRecovery::Manual`], it stays in bus-off until recovery is requested with
+    /// [`Can::recover_from_bus_off`].
+    ///
+    /// [`BusOffRecovery::Automatic`] is the default.
+    pub fn set_bus_off_recovery(self, recovery: BusOffRecovery) -> Self {
+        let can = self.can.registers();
+        can.mcr
+            .modify(|_, w| w.abom().bit(recovery == BusOffRecovery::Automatic));
+        self
+    }
+
+    /// Enables or disables debug freeze.
+    ///
+    /// If this is enabled, reception and transmission are stopped while the CPU is halted by a
+    /// debugger (eg. at a breakpoint). The peripheral resumes operation once the CPU continues.
+    /// If it is disabled, the peripheral keeps communicating while the CPU is halted.
+    ///
+    /// Debug freeze is enabled after reset. Use [`Can::debug_freeze`] to read the current setting.
+    pub fn set_debug_freeze(self, enabled: bool) -> Self {
+        let can = self.can.registers();
+        can.mcr.modify(|_, w| w.dbf().bit(enabled));
+        self
+    }
+
+    /// Enables or disables the receive FIFO lock.
+    ///
+    /// This determines which frame is lost when a frame arrives while its receive FIFO is full
+    /// (see [`OverrunPolicy`]):
+    ///
+    /// - If the FIFO is locked, the incoming frame is discarded ([`OverrunPolicy::Discard`]).
+    /// - If the FIFO is not locked, the incoming frame overwrites the most recently received frame
+    ///   in the FIFO ([`OverrunPolicy::Overwrite`]).
+    ///
+    /// The setting applies to both FIFOs. The FIFOs are not locked by default.
+    pub fn set_rx_fifo_locked(self, enabled: bool) -> Self {
+        let can = self.can.registers();
+        can.mcr.modify(|_, w| w.rflm().bit(enabled));
+        self
+    }
+
+    /// Configures the order in which pending frames are transmitted.
+    ///
+    /// By default, frames are transmitted in order of their priority ([`TransmitOrder::Priority`]).
+    pub fn set_transmit_order(self, order: TransmitOrder) -> Self {
+        let can = self.can.registers();
+        can.mcr
+            .modify(|_, w| w.txfp().bit(order == TransmitOrder::Fifo));
+        self
+    }
+
+    /// Enables or disables Time Triggered Communication Mode (TTCM).
+    ///
+    /// In this mode, the peripheral's internal 16-bit timer is running, which counts CAN bit
+    /// times. It is used to timestamp received frames (see [`Envelope::timestamp`]) and transmitted
+    /// frames (see [`Tx::transmit_timestamp`]), and can be inserted into outgoing frames with
+    /// [`Tx::transmit_with_timestamp`].
+    ///
+    /// Time Triggered Communication Mode is disabled by default.
+    pub fn set_time_triggered_mode(self, enabled: bool) -> Self {
+        let can = self.can.registers();
+        can.mcr.modify(|_, w| w.ttcm().bit(enabled));
+        self
+    }
+
     /// Leaves initialization mode and enables the peripheral.
     ///
     /// To sync with the CAN bus, this will block until 11 consecutive recessive bits are detected
@@ -493,6 +699,9 @@ impl<I: Instance> CanBuilder<I> {
 /// Interface to a bxCAN peripheral.
 pub struct Can<I: Instance> {
     instance: I,
+    /// How far [`Can::recover_from_bus_off`] has gotten with requesting recovery.
+    bus_off_recovery: RecoveryRequest,
+    tx: Tx<I>,
 }
 
 impl<I> Can<I>
@@ -502,7 +711,12 @@ where
     /// Creates a [`CanBuilder`] for constructing a CAN interface.
     pub fn builder(instance: I) -> CanBuilder<I> {
         let can_builder = CanBuilder {
-            can: Can { instance },
+            can: Can {
+                instance,
+                bus_off_recovery: RecoveryRequest::None,
+                // Safety: The `Can` instance owns the peripheral.
+                tx: unsafe { Tx::conjure() },
+            },
         };
 
         let can_reg = can_builder.can.registers();
@@ -517,6 +731,9 @@ where
             }
         }
 
+        // Default to automatic bus-off recovery.
+        can_reg.mcr.modify(|_, w| w.abom().set_bit());
+
         can_builder
     }
 
@@ -570,6 +787,23 @@ where
         CanConfig { can: self }
     }
 
+    /// Reads back the currently configured bit timing.
+    ///
+    /// The returned [`BitTiming`] also reports whether loopback and silent mode are enabled. This
+    /// can be used to log the active configuration, eg. the bitrate via [`BitTiming::bitrate`].
+    pub fn bit_timing(&self) -> BitTiming {
+        let can = self.registers();
+        BitTiming::from_btr(can.btr.read().bits())
+    }
+
+    /// Returns `true` if debug freeze is enabled.
+    ///
+    /// See [`CanConfig::set_debug_freeze`] for details.
+    pub fn debug_freeze(&self) -> bool {
+        let can = self.registers();
+        can.mcr.read().dbf().bit_is_set()
+    }
+
     /// Configures the automatic wake-up feature.
     ///
     /// This is turned off by default.
@@ -594,14 +828,70 @@ where
         let can = self.registers();
         let msr = can.msr.read();
         if msr.slak().bit_is_set() {
-            can.mcr
-                .modify(|_, w| w.abom().set_bit().sleep().clear_bit());
+            can.mcr.modify(|_, w| w.sleep().clear_bit());
             Err(nb::Error::WouldBlock)
         } else {
             Ok(())
         }
     }
 
+    /// Requests recovery from the bus-off state (non-blocking).
+    ///
+    /// This is needed when the peripheral is configured for [`BusOffRecovery::Manual`]. It allows
+    /// the application to decide when to rejoin the bus, for example after counting the number of
+    /// bus-off events.
+    ///
+    /// The first call while the peripheral is in bus-off requests recovery. After that, the
+    /// peripheral has to monitor 128 occurrences of 11 consecutive recessive bits on the bus
+    /// before it can rejoin it. This can be tracked with [`Can::bus_off_recovery_progress`].
+    ///
+    /// Returns [`WouldBlock`][nb::Error::WouldBlock] while recovery is in progress. Returns `Ok`
+    /// once the peripheral is no longer in bus-off.
+    pub fn recover_from_bus_off(&mut self) -> nb::Result<(), Infallible> {
+        if self.registers().esr.read().boff().bit_is_clear() {
+            if self.bus_off_recovery == RecoveryRequest::EnteringInit {
+                self.registers().mcr.modify(|_, w| w.inrq().clear_bit());
+            }
+            self.bus_off_recovery = RecoveryRequest::None;
+            return Ok(());
+        }
+
+        // Recovery is requested by entering and leaving initialization mode. Entering
+        // initialization mode should complete immediately, since there is no bus activity to wait
+        // for during bus-off, but the acknowledgement is checked again on the next call.
+        if self.bus_off_recovery == RecoveryRequest::None {
+            self.registers()
+                .mcr
+                .modify(|_, w| w.sleep().clear_bit().inrq().set_bit());
+            self.bus_off_recovery = RecoveryRequest::EnteringInit;
+        }
+        if self.bus_off_recovery == RecoveryRequest::EnteringInit
+            && self.registers().msr.read().inak().bit_is_set()
+        {
+            self.registers().mcr.modify(|_, w| w.inrq().clear_bit());
+            self.bus_off_recovery = RecoveryRequest::Requested;
+        }
+
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Returns the progress of the bus-off recovery sequence.
+    ///
+    /// While the peripheral is in bus-off, this returns the number of occurrences of 11
+    /// consecutive recessive bits monitored so far (which the peripheral counts in its receive
+    /// error counter). Recovery is complete once 128 occurrences have been monitored (and, with
+    /// [`BusOffRecovery::Manual`], once recovery was requested via [`Can::recover_from_bus_off`]).
+    ///
+    /// Returns `None` if the peripheral is not in bus-off.
+    pub fn bus_off_recovery_progress(&self) -> Option<u8> {
+        let esr = self.registers().esr.read();
+        if esr.boff().bit_is_set() {
+            Some(esr.rec().bits())
+        } else {
+            None
+        }
+    }
+
     /// Puts the peripheral in a sleep mode to save power.
     ///
     /// While in sleep mode, an incoming CAN frame will trigger [`Interrupt::Wakeup`] if enabled.
@@ -659,16 +949,14 @@ where
 
     /// Clears the pending flag of [`Interrupt::Sleep`].
     pub fn clear_sleep_interrupt(&self) {
-        let can = self.registers();
         // Read-only register with write-1-to-clear, so `&self` is sufficient.
-        can.msr.write(|w| w.slaki().set_bit());
+        clear_sleep_flag(self.registers());
     }
 
     /// Clears the pending flag of [`Interrupt::Wakeup`].
     pub fn clear_wakeup_interrupt(&self) {
-        let can = self.registers();
         // Read-only register with write-1-to-clear, so `&self` is sufficient.
-        can.msr.write(|w| w.wkui().set_bit());
+        clear_wakeup_flag(self.registers());
     }
 
     /// Clears the "Request Completed" (RQCP) flag of a transmit mailbox.
@@ -678,21 +966,13 @@ where
     ///
     /// Once this function returns `None`, a pending [`Interrupt::TransmitMailboxEmpty`] is
     /// considered acknowledged.
+    ///
+    /// To find out whether the frame was actually transmitted, use [`Can::poll_completion`]
+    /// instead.
     pub fn clear_request_completed_flag(&mut self) -> Option<Mailbox> {
-        let can = self.registers();
-        let tsr = can.tsr.read();
-        if tsr.rqcp0().bit_is_set() {
-            can.tsr.modify(|_, w| w.rqcp0().set_bit());
-            Some(Mailbox::Mailbox0)
-        } else if tsr.rqcp1().bit_is_set() {
-            can.tsr.modify(|_, w| w.rqcp1().set_bit());
-            Some(Mailbox::Mailbox1)
-        } else if tsr.rqcp2().bit_is_set() {
-            can.tsr.modify(|_, w| w.rqcp2().set_bit());
-            Some(Mailbox::Mailbox2)
-        } else {
-            None
-        }
+        self.tx
+            .poll_completion()
+            .map(|completion| completion.mailbox())
     }
 
     /// Clears a pending TX interrupt ([`Interrupt::TransmitMailboxEmpty`]).
@@ -703,13 +983,35 @@ where
         while self.clear_request_completed_flag().is_some() {}
     }
 
+    /// Returns the outcome of a finished transmit request, and clears its "Request Completed"
+    /// (RQCP) flag.
+    ///
+    /// See [`Tx::poll_completion`] for details.
+    pub fn poll_completion(&mut self) -> Option<TransmitCompletion> {
+        self.tx.poll_completion()
+    }
+
+    /// Returns the outcomes of all finished transmit requests, and clears their "Request
+    /// Completed" (RQCP) flags.
+    ///
+    /// See [`Tx::take_completions`] for details.
+    pub fn take_completions(&mut self) -> impl Iterator<Item = TransmitCompletion> {
+        self.tx.take_completions()
+    }
+
+    /// Returns the number of finished requests whose outcome was lost before it was polled.
+    ///
+    /// See [`Tx::lost_completions`] for details.
+    pub fn lost_completions(&self) -> u32 {
+        self.tx.lost_completions()
+    }
+
     /// Clears the error interrupt flag ([`Interrupt::Error`]).
     ///
     /// To read the error status, use [`Can::error_status`] to get the [`ErrorStatus`] before
     /// clearing the interrupt flag.
     pub fn clear_error_interrupt(&mut self) {
-        let can = self.registers();
-        can.msr.write(|w| w.erri().set_bit());
+        clear_error_flag(self.registers());
     }
 
     /// Reads the error status register's data.
@@ -748,15 +1050,54 @@ where
     /// lowest-priority message in the transmit mailboxes, transmission of the enqueued frame is
     /// cancelled and `frame` is enqueued instead. The frame that was replaced is returned as
     /// [`TransmitStatus::dequeued_frame`].
+    ///
+    /// If the peripheral is configured for [`TransmitOrder::Fifo`], frames are transmitted in the
+    /// order they are enqueued instead, and no frame is ever dequeued. If all transmit mailboxes
+    /// are full, this returns [`WouldBlock`][nb::Error::WouldBlock].
     pub fn transmit(&mut self, frame: &Frame) -> nb::Result<TransmitStatus, Infallible> {
-        // Safety: We have a `&mut self` and have unique access to the peripheral.
-        unsafe { Tx::<I>::conjure().transmit(frame) }
+        self.tx.transmit(frame)
+    }
+
+    /// Puts a CAN frame in a free transmit mailbox for transmission on the bus, and makes the
+    /// peripheral insert the transmission timestamp into the frame.
+    ///
+    /// See [`Tx::transmit_with_timestamp`] for details.
+    pub fn transmit_with_timestamp(
+        &mut self,
+        frame: &Frame,
+    ) -> nb::Result<TransmitStatus, Infallible> {
+        self.tx.transmit_with_timestamp(frame)
+    }
+
+    /// Puts a CAN frame in a free transmit mailbox for transmission on the bus, and aborts it if
+    /// it is still pending at `deadline`.
+    ///
+    /// See [`Tx::transmit_with_deadline`] for details.
+    pub fn transmit_with_deadline(
+        &mut self,
+        frame: &Frame,
+        deadline: Instant,
+    ) -> nb::Result<TransmitStatus, Infallible> {
+        self.tx.transmit_with_deadline(frame, deadline)
+    }
+
+    /// Aborts all pending frames whose deadline has passed at `now`.
+    ///
+    /// See [`Tx::abort_expired`] for details.
+    pub fn abort_expired(&mut self, now: Instant) -> impl Iterator<Item = (TxToken, Frame)> {
+        self.tx.abort_expired(now)
+    }
+
+    /// Returns the time at which the last frame in `mailbox` was transmitted.
+    ///
+    /// See [`Tx::transmit_timestamp`] for details.
+    pub fn transmit_timestamp(&self, mailbox: Mailbox) -> u16 {
+        self.tx.transmit_timestamp(mailbox)
     }
 
     /// Returns `true` if no frame is pending for transmission.
     pub fn is_transmitter_idle(&self) -> bool {
-        // Safety: Read-only operation.
-        unsafe { Tx::<I>::conjure().is_idle() }
+        self.tx.is_idle()
     }
 
     /// Attempts to abort the sending of a frame that is pending in a mailbox.
@@ -767,8 +1108,22 @@ where
     /// If there is a frame in the provided mailbox, and it is canceled successfully, this function
     /// returns `true`.
     pub fn abort(&mut self, mailbox: Mailbox) -> bool {
-        // Safety: We have a `&mut self` and have unique access to the peripheral.
-        unsafe { Tx::<I>::conjure().abort(mailbox) }
+        self.tx.abort(mailbox)
+    }
+
+    /// Returns the frames that are still waiting for transmission, along with their mailboxes.
+    ///
+    /// See [`Tx::pending`] for details.
+    pub fn pending(&self) -> impl Iterator<Item = (Mailbox, Frame)> {
+        self.tx.pending()
+    }
+
+    /// Aborts the transmission of all pending frames, and returns the tokens and frames of the
+    /// requests that were aborted successfully.
+    ///
+    /// See [`Tx::abort_all`] for details.
+    pub fn abort_all(&mut self) -> impl Iterator<Item = (TxToken, Frame)> {
+        self.tx.abort_all()
     }
 
     /// Returns a received frame if available.
@@ -778,12 +1133,22 @@ where
     ///
     /// Returns `Err` when a frame was lost due to buffer overrun.
     pub fn receive(&mut self) -> nb::Result<Frame, OverrunError> {
+        self.receive_with_meta().map(Envelope::into_frame)
+    }
+
+    /// Returns a received frame, along with its reception metadata, if available.
+    ///
+    /// Like [`Can::receive`], this will first check FIFO 0 and then FIFO 1. The [`Envelope`]
+    /// reports which FIFO the frame was taken from.
+    ///
+    /// Returns `Err` when a frame was lost due to buffer overrun.
+    pub fn receive_with_meta(&mut self) -> nb::Result<Envelope, OverrunError> {
         // Safety: We have a `&mut self` and have unique access to the peripheral.
         let mut rx0 = unsafe { Rx0::<I>::conjure() };
         let mut rx1 = unsafe { Rx1::<I>::conjure() };
 
-        match rx0.receive() {
-            Err(nb::Error::WouldBlock) => rx1.receive(),
+        match rx0.receive_with_meta() {
+            Err(nb::Error::WouldBlock) => rx1.receive_with_meta(),
             result => result,
         }
     }
@@ -802,8 +1167,8 @@ where
 
     /// Splits this `Can` instance into transmitting and receiving halves, by reference.
     pub fn split_by_ref(&mut self) -> (&mut Tx<I>, &mut Rx0<I>, &mut Rx1<I>) {
+        let tx = &mut self.tx;
         // Safety: We take `&mut self` and the return value lifetimes are tied to `self`'s lifetime.
-        let tx = unsafe { Tx::conjure_by_ref() };
         let rx0 = unsafe { Rx0::conjure_by_ref() };
         let rx1 = unsafe { Rx1::conjure_by_ref() };
         (tx, rx0, rx1)
@@ -812,7 +1177,7 @@ where
     /// Consumes this `Can` instance and splits it into transmitting and receiving halves.
     pub fn split(self) -> (Tx<I>, Rx0<I>, Rx1<I>) {
         // Safety: `Self` is not `Copy` and is destroyed by moving it into this method.
-        unsafe { (Tx::conjure(), Rx0::conjure(), Rx1::conjure()) }
+        unsafe { (self.tx, Rx0::conjure(), Rx1::conjure()) }
     }
 }
 
@@ -824,11 +1189,75 @@ impl<I: FilterOwner> Can<I> {
     pub fn modify_filters(&mut self) -> MasterFilters<'_, I> {
         unsafe { MasterFilters::new() }
     }
+
+    /// Accesses the filter banks owned by this CAN peripheral, without interrupting reception.
+    ///
+    /// See [`LiveFilters`] for how this differs from [`modify_filters`](Self::modify_filters).
+    pub fn live_filters(&mut self) -> LiveFilters<'_, I> {
+        unsafe { LiveFilters::new() }
+    }
 }
 
 /// Interface to the CAN transmitter part.
 pub struct Tx<I> {
     _can: PhantomData<I>,
+    /// The token handed out for the next transmit request.
+    next_token: u64,
+    /// The tokens of the last requests made in each mailbox.
+    tokens: [Option<TxToken>; 3],
+    /// Completed requests whose mailbox was reused before they were polled.
+    completions: CompletionQueue,
+    /// The number of completed requests that did not fit into `completions`.
+    lost_completions: u32,
+    /// The deadlines of the pending requests in each mailbox.
+    deadlines: [Option<Instant>; 3],
+    /// Whether automatic retransmission was disabled for the last request in each mailbox.
+    one_shot: [bool; 3],
+}
+
+/// Number of completed requests that [`Tx`] keeps after their mailbox was reused.
+const KEPT_COMPLETIONS: usize = 8;
+
+/// Completed requests kept by [`Tx`] until they are polled, oldest first.
+#[derive(Clone, Copy)]
+struct CompletionQueue {
+    entries: [Option<TransmitCompletion>; KEPT_COMPLETIONS],
+    head: usize,
+    len: usize,
+}
+
+impl CompletionQueue {
+    const fn new() -> Self {
+        Self {
+            entries: [None; KEPT_COMPLETIONS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends `completion`, or returns `false` if the queue is full.
+    fn push(&mut self, completion: TransmitCompletion) -> bool {
+        if self.len == KEPT_COMPLETIONS {
+            return false;
+        }
+        self.entries[(self.head + self.len) % KEPT_COMPLETIONS] = Some(completion);
+        self.len += 1;
+        true
+    }
+}
+
+impl Iterator for CompletionQueue {
+    type Item = TransmitCompletion;
+
+    fn next(&mut self) -> Option<TransmitCompletion> {
+        if self.len == 0 {
+            return None;
+        }
+        let completion = self.entries[self.head].take();
+        self.head = (self.head + 1) % KEPT_COMPLETIONS;
+        self.len -= 1;
+        completion
+    }
 }
 
 #[inline]
@@ -841,23 +1270,30 @@ const fn abort_mask(idx: usize) -> u32 {
     0x80 << (8 * idx)
 }
 
+#[inline]
+const fn rqcp_mask(idx: usize) -> u32 {
+    0x01 << (8 * idx)
+}
+
+#[inline]
+const fn tme_mask(idx: usize) -> u32 {
+    1 << (26 + idx)
+}
+
 impl<I> Tx<I>
 where
     I: Instance,
 {
     unsafe fn conjure() -> Self {
-        Self { _can: PhantomData }
-    }
-
-    /// Creates a `&mut Self` out of thin air.
-    ///
-    /// This is only safe if it is the only way to access a `Tx<I>`.
-    unsafe fn conjure_by_ref<'a>() -> &'a mut Self {
-        // Cause out of bounds access when `Self` is not zero-sized.
-        [()][core::mem::size_of::<Self>()];
-
-        // Any aligned pointer is valid for ZSTs.
-        &mut *NonNull::dangling().as_ptr()
+        Self {
+            _can: PhantomData,
+            next_token: 0,
+            tokens: [None; 3],
+            completions: CompletionQueue::new(),
+            lost_completions: 0,
+            deadlines: [None; 3],
+            one_shot: [false; 3],
+        }
     }
 
     fn registers(&self) -> &RegisterBlock {
@@ -873,7 +1309,59 @@ where
     /// lowest-priority message in the transmit mailboxes, transmission of the enqueued frame is
     /// cancelled and `frame` is enqueued instead. The frame that was replaced is returned as
     /// [`TransmitStatus::dequeued_frame`].
+    ///
+    /// If the peripheral is configured for [`TransmitOrder::Fifo`], frames are transmitted in the
+    /// order they are enqueued instead, and no frame is ever dequeued. If all transmit mailboxes
+    /// are full, this returns [`WouldBlock`][nb::Error::WouldBlock].
+    ///
+    /// Reusing a mailbox clears the outcome of its previous request, which is kept until it is
+    /// polled with [`Tx::poll_completion`] or [`Tx::take_completions`]. Only a limited number of
+    /// outcomes is kept, so poll regularly when transmitting many frames. Lost outcomes are counted
+    /// by [`Tx::lost_completions`].
     pub fn transmit(&mut self, frame: &Frame) -> nb::Result<TransmitStatus, Infallible> {
+        self.transmit_inner(frame, false, None)
+    }
+
+    /// Puts a CAN frame in a transmit mailbox for transmission on the bus, and makes the
+    /// peripheral insert the transmission timestamp into the frame.
+    ///
+    /// This requires Time Triggered Communication Mode to be enabled (see
+    /// [`CanConfig::set_time_triggered_mode`]). The peripheral will then overwrite the last two
+    /// data bytes of the frame with the value of its 16-bit timer at the start of transmission
+    /// (byte 6 holds the high and byte 7 the low byte of the timer value). The timestamp is
+    /// only inserted into data frames with a length of 8 bytes, other frames are transmitted
+    /// unmodified.
+    ///
+    /// Apart from that, this behaves like [`Tx::transmit`].
+    pub fn transmit_with_timestamp(
+        &mut self,
+        frame: &Frame,
+    ) -> nb::Result<TransmitStatus, Infallible> {
+        self.transmit_inner(frame, true, None)
+    }
+
+    /// Puts a CAN frame in a transmit mailbox for transmission on the bus, and aborts it if it is
+    /// still pending at `deadline`.
+    ///
+    /// The deadline is enforced by [`Tx::abort_expired`], which has to be called periodically, for
+    /// example from a timer interrupt. If `frame` dequeues another frame, or is dequeued itself,
+    /// the deadline of the dequeued frame no longer applies.
+    ///
+    /// Apart from that, this behaves like [`Tx::transmit`].
+    pub fn transmit_with_deadline(
+        &mut self,
+        frame: &Frame,
+        deadline: Instant,
+    ) -> nb::Result<TransmitStatus, Infallible> {
+        self.transmit_inner(frame, false, Some(deadline))
+    }
+
+    fn transmit_inner(
+        &mut self,
+        frame: &Frame,
+        insert_timestamp: bool,
+        deadline: Option<Instant>,
+    ) -> nb::Result<TransmitStatus, Infallible> {
         let can = self.registers();
 
         // Get the index of the next free mailbox or the one with the lowest priority.
@@ -882,7 +1370,14 @@ where
 
         let frame_is_pending =
             tsr.tme0().bit_is_clear() || tsr.tme1().bit_is_clear() || tsr.tme2().bit_is_clear();
-        let pending_frame = if frame_is_pending {
+        let pending_frame = if can.mcr.read().txfp().bit_is_set() {
+            // The peripheral transmits frames in the order they were requested, so there is no
+            // priority inversion to protect against. Just use the next free mailbox, if any.
+            if tsr.tme0().bit_is_clear() && tsr.tme1().bit_is_clear() && tsr.tme2().bit_is_clear() {
+                return Err(nb::Error::WouldBlock);
+            }
+            None
+        } else if frame_is_pending {
             // High priority frames are transmitted first by the mailbox system.
             // Frames with identical identifier shall be transmitted in FIFO order.
             // The controller schedules pending frames of same priority based on the
@@ -909,17 +1404,31 @@ where
             None
         };
 
-        self.write_mailbox(idx, frame);
-
-        let mailbox = match idx {
-            0 => Mailbox::Mailbox0,
-            1 => Mailbox::Mailbox1,
-            2 => Mailbox::Mailbox2,
-            _ => unreachable!(),
+        let dequeued_token = if pending_frame.is_some() {
+            self.tokens[idx]
+        } else {
+            // Setting TXRQ clears the flags of the last request in this mailbox, so keep its
+            // outcome around until it is polled.
+            let tsr = self.registers().tsr.read().bits();
+            if tsr & rqcp_mask(idx) != 0 {
+                self.keep_completion(self.completion(tsr, idx));
+            }
+            None
         };
+
+        self.write_mailbox(idx, frame, insert_timestamp);
+
+        let token = TxToken(self.next_token);
+        self.next_token += 1;
+        self.tokens[idx] = Some(token);
+        self.deadlines[idx] = deadline;
+        self.one_shot[idx] = self.registers().mcr.read().nart().bit_is_set();
+
         Ok(TransmitStatus {
             dequeued_frame: pending_frame,
-            mailbox,
+            dequeued_token,
+            mailbox: Mailbox::from_index(idx),
+            token,
         })
     }
 
@@ -943,14 +1452,14 @@ where
         Ok(())
     }
 
-    fn write_mailbox(&mut self, idx: usize, frame: &Frame) {
+    fn write_mailbox(&mut self, idx: usize, frame: &Frame, insert_timestamp: bool) {
         let can = self.registers();
 
         debug_assert!(idx < 3);
         let mb = unsafe { &can.tx.get_unchecked(idx) };
 
         mb.tdtr
-            .write(|w| unsafe { w.dlc().bits(frame.dlc() as u8) });
+            .write(|w| unsafe { w.dlc().bits(frame.dlc()).tgt().bit(insert_timestamp) });
         mb.tdlr.write(|w| unsafe {
             w.bits(u32::from_ne_bytes(
                 frame.data.bytes[0..4].try_into().unwrap(),
@@ -967,20 +1476,7 @@ where
 
     fn read_pending_mailbox(&mut self, idx: usize) -> Option<Frame> {
         if self.abort_by_index(idx) {
-            let can = self.registers();
-            debug_assert!(idx < 3);
-            let mb = unsafe { &can.tx.get_unchecked(idx) };
-
-            // Read back the pending frame.
-            let mut pending_frame = Frame {
-                id: IdReg(mb.tir.read().bits()),
-                data: Data::empty(),
-            };
-            pending_frame.data.bytes[0..4].copy_from_slice(&mb.tdlr.read().bits().to_ne_bytes());
-            pending_frame.data.bytes[4..8].copy_from_slice(&mb.tdhr.read().bits().to_ne_bytes());
-            pending_frame.data.len = mb.tdtr.read().dlc().bits();
-
-            Some(pending_frame)
+            Some(self.read_mailbox(idx))
         } else {
             // Abort request failed because the frame was already sent (or being sent) on
             // the bus. All mailboxes are now free. This can happen for small prescaler
@@ -990,6 +1486,27 @@ where
         }
     }
 
+    /// Reads back the frame in a transmit mailbox.
+    fn read_mailbox(&self, idx: usize) -> Frame {
+        let can = self.registers();
+        debug_assert!(idx < 3);
+        let mb = unsafe { &can.tx.get_unchecked(idx) };
+
+        let mut frame = Frame {
+            id: IdReg::from_register(mb.tir.read().bits()),
+            data: Data::empty(),
+        };
+        frame.data.bytes[0..4].copy_from_slice(&mb.tdlr.read().bits().to_ne_bytes());
+        frame.data.bytes[4..8].copy_from_slice(&mb.tdhr.read().bits().to_ne_bytes());
+        frame.data.len = mb.tdtr.read().dlc().bits();
+        frame
+    }
+
+    /// Returns `true` if the mailbox with index `idx` is empty.
+    fn is_mailbox_empty(&self, idx: usize) -> bool {
+        self.registers().tsr.read().bits() & tme_mask(idx) != 0
+    }
+
     /// Tries to abort a pending frame. Returns `true` when aborted.
     fn abort_by_index(&mut self, idx: usize) -> bool {
         let can = self.registers();
@@ -1028,6 +1545,79 @@ where
         }
     }
 
+    /// Aborts all pending frames whose deadline has passed at `now`.
+    ///
+    /// Returns the tokens and frames of the requests that were aborted. Frames whose transmission
+    /// succeeds before they can be aborted are not returned. Aborted requests are also reported
+    /// as [`TransmitResult::Aborted`] by [`Tx::poll_completion`] and [`Tx::take_completions`].
+    ///
+    /// `now` has to come from the same time source as the deadlines passed to
+    /// [`Tx::transmit_with_deadline`].
+    pub fn abort_expired(&mut self, now: Instant) -> impl Iterator<Item = (TxToken, Frame)> {
+        let aborted = [0, 1, 2].map(|idx| {
+            let deadline = self.deadlines[idx]?;
+            if self.is_mailbox_empty(idx) {
+                self.deadlines[idx] = None;
+                return None;
+            }
+            if !now.has_reached(deadline) {
+                return None;
+            }
+
+            self.deadlines[idx] = None;
+            if self.abort_by_index(idx) {
+                Some((self.tokens[idx]?, self.read_mailbox(idx)))
+            } else {
+                None
+            }
+        });
+        aborted.into_iter().flatten()
+    }
+
+    /// Returns the frames that are still waiting for transmission, along with their mailboxes.
+    ///
+    /// Frames are returned in mailbox order, which is not necessarily the order in which they will
+    /// be transmitted. Since the peripheral keeps transmitting, a returned frame may already have
+    /// been sent by the time it is inspected.
+    pub fn pending(&self) -> impl Iterator<Item = (Mailbox, Frame)> {
+        let pending = [0, 1, 2].map(|idx| {
+            if self.is_mailbox_empty(idx) {
+                None
+            } else {
+                Some((Mailbox::from_index(idx), self.read_mailbox(idx)))
+            }
+        });
+        pending.into_iter().flatten()
+    }
+
+    /// Aborts the transmission of all pending frames.
+    ///
+    /// Returns the tokens and frames of the requests that were aborted successfully, in mailbox
+    /// order. Frames whose transmission succeeds before they can be aborted are not returned.
+    /// Every aborted request is also reported as [`TransmitResult::Aborted`] by
+    /// [`Tx::poll_completion`] and [`Tx::take_completions`].
+    pub fn abort_all(&mut self) -> impl Iterator<Item = (TxToken, Frame)> {
+        let aborted = [0, 1, 2].map(|idx| {
+            if !self.is_mailbox_empty(idx) && self.abort_by_index(idx) {
+                Some((self.tokens[idx]?, self.read_mailbox(idx)))
+            } else {
+                None
+            }
+        });
+        aborted.into_iter().flatten()
+    }
+
+    /// Returns the time at which the last frame in `mailbox` was transmitted.
+    ///
+    /// This is the value of the peripheral's 16-bit timer captured at the start of the frame
+    /// transmission. It is only updated while Time Triggered Communication Mode is enabled (see
+    /// [`CanConfig::set_time_triggered_mode`]), and is only valid once the mailbox has finished
+    /// transmission (eg. after [`Interrupt::TransmitMailboxEmpty`] fired for it).
+    pub fn transmit_timestamp(&self, mailbox: Mailbox) -> u16 {
+        let can = self.registers();
+        can.tx[mailbox as usize].tdtr.read().time().bits()
+    }
+
     /// Returns `true` if no frame is pending for transmission.
     pub fn is_idle(&self) -> bool {
         let can = self.registers();
@@ -1035,8 +1625,115 @@ where
         tsr.tme0().bit_is_set() && tsr.tme1().bit_is_set() && tsr.tme2().bit_is_set()
     }
 
+    /// Returns the outcome of a finished transmit request, and clears its "Request Completed"
+    /// (RQCP) flag.
+    ///
+    /// Returns `None` if no mailbox has finished a request since its flag was last cleared. Once
+    /// this returns `None`, a pending [`Interrupt::TransmitMailboxEmpty`] is considered
+    /// acknowledged.
+    pub fn poll_completion(&mut self) -> Option<TransmitCompletion> {
+        if let Some(completion) = self.completions.next() {
+            return Some(completion);
+        }
+
+        let can = self.registers();
+        let tsr = can.tsr.read().bits();
+        let idx = (0..3).find(|&idx| tsr & rqcp_mask(idx) != 0)?;
+        can.tsr.write(|w| unsafe { w.bits(rqcp_mask(idx)) });
+        Some(self.completion(tsr, idx))
+    }
+
+    /// Returns the outcomes of all finished transmit requests, and clears their "Request
+    /// Completed" (RQCP) flags.
+    ///
+    /// Completions are returned in mailbox order, except that completions of requests whose mailbox
+    /// has already been reused come first. This acknowledges a pending
+    /// [`Interrupt::TransmitMailboxEmpty`], unless another request finishes concurrently.
+    pub fn take_completions(&mut self) -> impl Iterator<Item = TransmitCompletion> {
+        let earlier = core::mem::replace(&mut self.completions, CompletionQueue::new());
+        let can = self.registers();
+        let tsr = can.tsr.read().bits();
+        let flags = tsr & (rqcp_mask(0) | rqcp_mask(1) | rqcp_mask(2));
+        if flags != 0 {
+            can.tsr.write(|w| unsafe { w.bits(flags) });
+        }
+
+        let completions = [0, 1, 2].map(|idx| {
+            if tsr & rqcp_mask(idx) != 0 {
+                Some(self.completion(tsr, idx))
+            } else {
+                None
+            }
+        });
+        earlier.chain(completions.into_iter().flatten())
+    }
+
+    /// Keeps the outcomes of all finished requests until they are polled, and clears their
+    /// "Request Completed" (RQCP) flags to acknowledge [`Interrupt::TransmitMailboxEmpty`].
+    ///
+    #[cfg(any(test, feature = "async"))]
+    pub(crate) fn stash_completions(&mut self) {
+        let can = self.registers();
+        let tsr = can.tsr.read().bits();
+        let flags = tsr & (rqcp_mask(0) | rqcp_mask(1) | rqcp_mask(2));
+        if flags == 0 {
+            return;
+        }
+
+        can.tsr.write(|w| unsafe { w.bits(flags) });
+        for idx in 0..3 {
+            if tsr & rqcp_mask(idx) != 0 {
+                self.keep_completion(self.completion(tsr, idx));
+            }
+        }
+    }
+
+    /// Keeps the outcome of a request whose RQCP flag is about to be cleared.
+    fn keep_completion(&mut self, completion: TransmitCompletion) {
+        if !self.completions.push(completion) {
+            self.lost_completions = self.lost_completions.wrapping_add(1);
+        }
+    }
+
+    /// Returns the number of finished requests whose outcome was lost, because their mailbox was
+    /// reused while too many other outcomes were waiting to be polled.
+    ///
+    /// Up to 8 outcomes are kept for requests whose mailbox was reused before they were polled
+    /// with [`Tx::poll_completion`] or [`Tx::take_completions`]. Further outcomes are dropped and
+    /// counted here. The counter wraps around on overflow.
+    pub fn lost_completions(&self) -> u32 {
+        self.lost_completions
+    }
+
+    /// Decodes the outcome of the completed request in mailbox `idx` from the value of TSR.
+    fn completion(&self, tsr: u32, idx: usize) -> TransmitCompletion {
+        // NART may have changed since the request was made.
+        let one_shot = self.one_shot[idx];
+        let flags = tsr >> (8 * idx);
+        // With automatic retransmission, a request only completes unsuccessfully when it is
+        // aborted. ALST and TERR then refer to an earlier attempt.
+        let result = if tsr & ok_mask(idx) != 0 {
+            TransmitResult::Transmitted
+        } else if one_shot && flags & 0x04 != 0 {
+            TransmitResult::ArbitrationLost
+        } else if one_shot && flags & 0x08 != 0 {
+            TransmitResult::Error
+        } else {
+            TransmitResult::Aborted
+        };
+        TransmitCompletion {
+            mailbox: Mailbox::from_index(idx),
+            result,
+            token: self.tokens[idx],
+        }
+    }
+
     /// Clears the request complete flag for all mailboxes.
+    ///
+    /// This discards the outcomes of all finished requests that have not been polled with
+    /// [`Tx::poll_completion`] or [`Tx::take_completions`].
     pub fn clear_interrupt_flags(&mut self) {
+        self.completions = CompletionQueue::new();
         let can = self.registers();
         can.tsr
             .write(|w| w.rqcp2().set_bit().rqcp1().set_bit().rqcp0().set_bit());
@@ -1071,7 +1768,14 @@ where
     ///
     /// Returns `Err` when a frame was lost due to buffer overrun.
     pub fn receive(&mut self) -> nb::Result<Frame, OverrunError> {
-        receive_fifo(self.registers(), 0)
+        self.receive_with_meta().map(Envelope::into_frame)
+    }
+
+    /// Returns a received frame, along with its reception metadata, if available.
+    ///
+    /// Returns `Err` when a frame was lost due to buffer overrun.
+    pub fn receive_with_meta(&mut self) -> nb::Result<Envelope, OverrunError> {
+        receive_fifo(self.registers(), Fifo::Fifo0)
     }
 
     fn registers(&self) -> &RegisterBlock {
@@ -1107,7 +1811,14 @@ where
     ///
     /// Returns `Err` when a frame was lost due to buffer overrun.
     pub fn receive(&mut self) -> nb::Result<Frame, OverrunError> {
-        receive_fifo(self.registers(), 1)
+        self.receive_with_meta().map(Envelope::into_frame)
+    }
+
+    /// Returns a received frame, along with its reception metadata, if available.
+    ///
+    /// Returns `Err` when a frame was lost due to buffer overrun.
+    pub fn receive_with_meta(&mut self) -> nb::Result<Envelope, OverrunError> {
+        receive_fifo(self.registers(), Fifo::Fifo1)
     }
 
     fn registers(&self) -> &RegisterBlock {
@@ -1115,8 +1826,23 @@ where
     }
 }
 
-fn receive_fifo(can: &RegisterBlock, fifo_nr: usize) -> nb::Result<Frame, OverrunError> {
-    assert!(fifo_nr < 2);
+/// Clears the pending flag of [`Interrupt::Sleep`].
+pub(crate) fn clear_sleep_flag(can: &RegisterBlock) {
+    can.msr.write(|w| w.slaki().set_bit());
+}
+
+/// Clears the pending flag of [`Interrupt::Wakeup`].
+pub(crate) fn clear_wakeup_flag(can: &RegisterBlock) {
+    can.msr.write(|w| w.wkui().set_bit());
+}
+
+/// Clears the pending flag of [`Interrupt::Error`].
+pub(crate) fn clear_error_flag(can: &RegisterBlock) {
+    can.msr.write(|w| w.erri().set_bit());
+}
+
+fn receive_fifo(can: &RegisterBlock, fifo: Fifo) -> nb::Result<Envelope, OverrunError> {
+    let fifo_nr = fifo as usize;
     let rfr = &can.rfr[fifo_nr];
     let rx = &can.rx[fifo_nr];
 
@@ -1129,7 +1855,12 @@ fn receive_fifo(can: &RegisterBlock, fifo_nr: usize) -> nb::Result<Frame, Overru
     // Check for RX FIFO overrun.
     if rfr_read.fovr().bit_is_set() {
         rfr.write(|w| w.fovr().set_bit());
-        return Err(nb::Error::Other(OverrunError { _priv: () }));
+        let policy = if can.mcr.read().rflm().bit_is_set() {
+            OverrunPolicy::Discard
+        } else {
+            OverrunPolicy::Overwrite
+        };
+        return Err(nb::Error::Other(OverrunError { fifo, policy }));
     }
 
     // Read the frame.
@@ -1139,12 +1870,72 @@ fn receive_fifo(can: &RegisterBlock, fifo_nr: usize) -> nb::Result<Frame, Overru
     };
     frame.data[0..4].copy_from_slice(&rx.rdlr.read().bits().to_ne_bytes());
     frame.data[4..8].copy_from_slice(&rx.rdhr.read().bits().to_ne_bytes());
-    frame.data.len = rx.rdtr.read().dlc().bits();
+    let rdtr = rx.rdtr.read();
+    frame.data.len = rdtr.dlc().bits();
 
     // Release the mailbox.
     rfr.write(|w| w.rfom().set_bit());
 
-    Ok(frame)
+    Ok(Envelope {
+        frame,
+        timestamp: rdtr.time().bits(),
+        filter_match_index: rdtr.fmi().bits(),
+        fifo,
+    })
+}
+
+/// A received frame, along with the metadata recorded by the peripheral on reception.
+///
+/// Returned by [`Can::receive_with_meta`], [`Rx0::receive_with_meta`] and
+/// [`Rx1::receive_with_meta`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct Envelope {
+    frame: Frame,
+    timestamp: u16,
+    filter_match_index: u8,
+    fifo: Fifo,
+}
+
+impl Envelope {
+    /// Returns the received frame.
+    #[inline]
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Consumes the envelope and returns the received frame.
+    #[inline]
+    pub fn into_frame(self) -> Frame {
+        self.frame
+    }
+
+    /// Returns the value of the peripheral's 16-bit timer captured at the start of frame
+    /// reception.
+    ///
+    /// The timer counts CAN bit times. It is only running (and this value is only meaningful)
+    /// while Time Triggered Communication Mode is enabled.
+    #[inline]
+    pub fn timestamp(&self) -> u16 {
+        self.timestamp
+    }
+
+    /// Returns the filter match index (FMI) of the filter that accepted the frame.
+    ///
+    /// Filters are numbered consecutively across all filter banks assigned to the frame's FIFO, in
+    /// the order of their bank index. Every 32-bit mask occupies one number, 16-bit masks and
+    /// 32-bit list entries occupy two numbers per bank, and 16-bit list entries occupy four
+    /// numbers per bank. This numbering also includes banks that are currently deactivated.
+    #[inline]
+    pub fn filter_match_index(&self) -> u8 {
+        self.filter_match_index
+    }
+
+    /// Returns the receive FIFO the frame was taken from.
+    #[inline]
+    pub fn fifo(&self) -> Fifo {
+        self.fifo
+    }
 }
 
 /// Identifies one of the two receive FIFOs.
@@ -1155,6 +1946,51 @@ pub enum Fifo {
     Fifo1 = 1,
 }
 
+/// How the peripheral recovers from the bus-off state.
+///
+/// Configured with [`CanBuilder::set_bus_off_recovery`] or [`CanConfig::set_bus_off_recovery`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum BusOffRecovery {
+    /// The peripheral leaves bus-off on its own, once it has monitored 128 occurrences of 11
+    /// consecutive recessive bits on the bus.
+    Automatic,
+    /// The peripheral stays in bus-off until recovery is requested with
+    /// [`Can::recover_from_bus_off`]. It then leaves bus-off once it has monitored 128 occurrences
+    /// of 11 consecutive recessive bits on the bus.
+    Manual,
+}
+
+/// The state of a bus-off recovery request made by [`Can::recover_from_bus_off`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RecoveryRequest {
+    /// Recovery has not been requested.
+    None,
+    /// Initialization mode was requested, and is waiting to be acknowledged.
+    EnteringInit,
+    /// Initialization mode was left, which requests recovery.
+    Requested,
+}
+
+/// The order in which pending frames are transmitted.
+///
+/// Configured with [`CanBuilder::set_transmit_order`] or [`CanConfig::set_transmit_order`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum TransmitOrder {
+    /// Frames are transmitted in order of their priority (see [`FramePriority`]).
+    ///
+    /// [`Tx::transmit`] protects against priority inversion and may dequeue a pending frame of
+    /// lower priority to make room for a new one.
+    Priority,
+    /// Frames are transmitted in the order they were passed to [`Tx::transmit`], regardless of
+    /// their priority.
+    ///
+    /// This is useful for protocols that send many segments with the same ID. [`Tx::transmit`]
+    /// never dequeues pending frames in this mode.
+    Fifo,
+}
+
 /// Identifies one of the three transmit mailboxes.
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 #[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
@@ -1167,11 +2003,24 @@ pub enum Mailbox {
     Mailbox2 = 2,
 }
 
+impl Mailbox {
+    fn from_index(idx: usize) -> Self {
+        match idx {
+            0 => Mailbox::Mailbox0,
+            1 => Mailbox::Mailbox1,
+            2 => Mailbox::Mailbox2,
+            _ => unreachable!(),
+        }
+    }
+}
+
 /// Contains information about a frame enqueued for transmission via [`Can::transmit`] or
 /// [`Tx::transmit`].
 pub struct TransmitStatus {
     dequeued_frame: Option<Frame>,
+    dequeued_token: Option<TxToken>,
     mailbox: Mailbox,
+    token: TxToken,
 }
 
 impl TransmitStatus {
@@ -1181,9 +2030,534 @@ impl TransmitStatus {
         self.dequeued_frame.as_ref()
     }
 
+    /// Returns the token of the frame that was dequeued to make space for the new frame.
+    ///
+    /// The dequeued request does not show up in [`Tx::poll_completion`] or
+    /// [`Tx::take_completions`]. Returns `None` if no frame was dequeued, or if it was not
+    /// enqueued through this transmitter.
+    #[inline]
+    pub fn dequeued_token(&self) -> Option<TxToken> {
+        self.dequeued_token
+    }
+
     /// Returns the [`Mailbox`] the frame was enqueued in.
     #[inline]
     pub fn mailbox(&self) -> Mailbox {
         self.mailbox
     }
+
+    /// Returns the token identifying this transmit request.
+    ///
+    /// The same token is reported when the request completes, or when the frame is dequeued again
+    /// in favor of a higher-priority frame.
+    #[inline]
+    pub fn token(&self) -> TxToken {
+        self.token
+    }
+}
+
+/// Identifies a transmit request.
+///
+/// Every frame enqueued with [`Tx::transmit`] (or [`Can::transmit`]) is assigned a new token. Tokens
+/// handed out by the same transmitter are unique and increase monotonically, so they can be used
+/// to tell apart requests that used the same [`Mailbox`] one after another.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct TxToken(u64);
+
+/// A point in time, used for transmit deadlines.
+///
+/// Instants come from a free-running 32-bit tick counter chosen by the application, such as a
+/// hardware timer or a system tick count, which is allowed to wrap around.
+///
+/// The peripheral's own 16-bit timer can not be used, since it is only captured in the timestamps
+/// of frames, and can not be read while the bus is idle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct Instant(u32);
+
+impl Instant {
+    /// Creates an instant from the value of a 32-bit tick counter.
+    ///
+    /// Instants are compared with wrapping arithmetic, so they must be less than 2<sup>31</sup>
+    /// ticks apart.
+    #[inline]
+    pub const fn from_ticks(ticks: u32) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns `true` if `self` is at or after `deadline`.
+    fn has_reached(self, deadline: Instant) -> bool {
+        (self.0.wrapping_sub(deadline.0) as i32) >= 0
+    }
+}
+
+/// The outcome of a transmit request.
+///
+/// Returned as part of a [`TransmitCompletion`] by [`Tx::poll_completion`] and
+/// [`Tx::take_completions`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub enum TransmitResult {
+    /// The frame was transmitted successfully.
+    Transmitted,
+    /// The frame lost arbitration against another frame on the bus, and was not retried.
+    ///
+    /// This is only reported when automatic retransmission is disabled.
+    ArbitrationLost,
+    /// Transmission of the frame failed due to a bus error, and was not retried.
+    ///
+    /// This is only reported when automatic retransmission is disabled. [`Can::error_status`]
+    /// may contain more details about the error.
+    Error,
+    /// The request was aborted before the frame was transmitted.
+    Aborted,
+}
+
+/// A finished transmit request, returned by [`Tx::poll_completion`] and
+/// [`Tx::take_completions`].
+///
+/// If too many requests finish without being polled while their mailboxes are reused, some of
+/// them are not reported, see [`Tx::lost_completions`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "unstable-defmt", derive(defmt::Format))]
+pub struct TransmitCompletion {
+    mailbox: Mailbox,
+    result: TransmitResult,
+    token: Option<TxToken>,
+}
+
+impl TransmitCompletion {
+    /// Returns the [`Mailbox`] that held the frame.
+    #[inline]
+    pub fn mailbox(&self) -> Mailbox {
+        self.mailbox
+    }
+
+    /// Returns the outcome of the request.
+    #[inline]
+    pub fn result(&self) -> TransmitResult {
+        self.result
+    }
+
+    /// Returns the token that [`Tx::transmit`] handed out for the request.
+    ///
+    /// Returns `None` if the frame was not enqueued through this transmitter.
+    #[inline]
+    pub fn token(&self) -> Option<TxToken> {
+        self.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::filter::{ListEntry32, Mask32};
+    use crate::sim::Sim;
+    use std::vec::Vec;
+
+    fn loopback<I: Instance>(instance: I) -> Can<I> {
+        Can::builder(instance)
+            .set_loopback(true)
+            .set_silent(true)
+            .enable()
+    }
+
+    #[test]
+    fn roundtrip_with_meta() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can1);
+
+        let id0 = StandardId::new(13).unwrap();
+        let id1 = StandardId::new(12).unwrap();
+        can.modify_filters().clear().enable_bank(
+            0,
+            Fifo::Fifo1,
+            [
+                ListEntry32::data_frames_with_id(id0),
+                ListEntry32::data_frames_with_id(id1),
+            ],
+        );
+
+        let frame = Frame::new_data(id1, [1, 2, 3]);
+        can.transmit(&frame).unwrap();
+        assert!(!can.is_transmitter_idle());
+
+        sim.advance_time(1000);
+        assert!(sim.step());
+        assert!(can.is_transmitter_idle());
+
+        let envelope = can.receive_with_meta().unwrap();
+        assert_eq!(*envelope.frame(), frame);
+        assert_eq!(envelope.fifo(), Fifo::Fifo1);
+        assert_eq!(envelope.filter_match_index(), 1);
+        assert_eq!(envelope.timestamp(), 1000);
+        assert!(can.receive().is_err());
+
+        // Frames that no filter accepts are dropped.
+        can.transmit(&Frame::new_data(id0, [])).unwrap();
+        can.transmit(&Frame::new_remote(id1, 0)).unwrap();
+        assert_eq!(sim.run(), 2);
+        assert_eq!(can.receive().unwrap(), Frame::new_data(id0, []));
+        assert!(can.receive().is_err());
+    }
+
+    #[test]
+    fn transmit_with_timestamp() {
+        let (mut sim, p) = Sim::new();
+        let mut can = Can::builder(p.can1)
+            .set_loopback(true)
+            .set_silent(true)
+            .set_time_triggered_mode(true)
+            .enable();
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        sim.advance_time(0x1234);
+        let frame = Frame::new_data(StandardId::new(1).unwrap(), [0; 8]);
+        let status = can.transmit_with_timestamp(&frame).unwrap();
+        sim.step();
+        assert_eq!(can.transmit_timestamp(status.mailbox()), 0x1234);
+
+        let received = can.receive().unwrap();
+        assert_eq!(received.data().unwrap()[6..], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn fifo_transmit_order() {
+        let (mut sim, p) = Sim::new();
+        let mut can = Can::builder(p.can3)
+            .set_loopback(true)
+            .set_silent(true)
+            .set_transmit_order(TransmitOrder::Fifo)
+            .enable();
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        let frames = [3, 2, 1].map(|id| Frame::new_data(ExtendedId::new(id).unwrap(), []));
+        for frame in &frames {
+            assert!(can.transmit(frame).unwrap().dequeued_frame().is_none());
+        }
+        let frame0 = Frame::new_data(ExtendedId::new(0).unwrap(), []);
+        assert!(can.transmit(&frame0).is_err());
+
+        assert_eq!(sim.run(), 3);
+        for frame in &frames {
+            assert_eq!(can.receive().unwrap(), *frame);
+        }
+    }
+
+    #[test]
+    fn rx_fifo_overrun() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can1);
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        let frames = [1, 2, 3, 4].map(|id| Frame::new_data(StandardId::new(id).unwrap(), []));
+
+        for (locked, policy, expected) in [
+            (false, OverrunPolicy::Overwrite, [0, 1, 3]),
+            (true, OverrunPolicy::Discard, [0, 1, 2]),
+        ] {
+            can.modify_config().set_rx_fifo_locked(locked).enable();
+
+            for frame in &frames {
+                can.transmit(frame).unwrap();
+                sim.step();
+            }
+
+            let err = match can.rx0().receive() {
+                Err(nb::Error::Other(err)) => err,
+                _ => panic!("expected overrun"),
+            };
+            assert_eq!(err.fifo(), Fifo::Fifo0);
+            assert_eq!(err.policy(), policy);
+
+            for i in expected {
+                assert_eq!(can.rx0().receive().unwrap(), frames[i]);
+            }
+            assert!(can.receive().is_err());
+        }
+    }
+
+    #[test]
+    fn dequeue_lower_priority_frame() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        // Enqueue several frames with increasing priorities.
+        let frames = [3, 2, 1].map(|id| Frame::new_data(ExtendedId::new(id).unwrap(), []));
+        for (frame, mailbox) in
+            frames
+                .iter()
+                .zip([Mailbox::Mailbox0, Mailbox::Mailbox1, Mailbox::Mailbox2])
+        {
+            let status = can.transmit(frame).unwrap();
+            assert!(status.dequeued_frame().is_none());
+            assert_eq!(status.mailbox(), mailbox);
+        }
+
+        // Frames of lower or equal priority have to wait.
+        assert!(can.transmit(&frames[0]).is_err());
+
+        // A higher-priority frame replaces the lowest-priority one.
+        let frame0 = Frame::new_data(ExtendedId::new(0).unwrap(), []);
+        let status = can.transmit(&frame0).unwrap();
+        assert_eq!(status.dequeued_frame(), Some(&frames[0]));
+        assert_eq!(status.mailbox(), Mailbox::Mailbox0);
+
+        assert_eq!(sim.run(), 3);
+        assert_eq!(can.receive().unwrap(), frame0);
+        assert_eq!(can.receive().unwrap(), frames[2]);
+        assert_eq!(can.receive().unwrap(), frames[1]);
+        assert!(can.receive().is_err());
+    }
+
+    #[test]
+    fn abort() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        let frame = Frame::new_data(StandardId::new(1).unwrap(), []);
+        let status = can.transmit(&frame).unwrap();
+        assert!(!can.abort(Mailbox::Mailbox1));
+        assert!(can.abort(status.mailbox()));
+        assert!(can.is_transmitter_idle());
+        assert!(!can.abort(status.mailbox()));
+
+        assert_eq!(sim.run(), 0);
+        assert!(can.receive().is_err());
+    }
+
+    #[test]
+    fn transmit_results() {
+        let (mut sim, p) = Sim::new();
+        fn one_shot<I: Instance>(instance: I) -> Can<I> {
+            Can::builder(instance)
+                .set_automatic_retransmit(false)
+                .enable()
+        }
+        fn completion<I: Instance>(can: &mut Can<I>) -> Option<(Mailbox, TransmitResult)> {
+            can.poll_completion()
+                .map(|completion| (completion.mailbox(), completion.result()))
+        }
+        let mut can3 = one_shot(p.can3);
+        let frame = |id| Frame::new_data(StandardId::new(id).unwrap(), []);
+
+        // Nobody acknowledges the frame.
+        can3.transmit(&frame(1)).unwrap();
+        assert!(!sim.step());
+        assert_eq!(
+            completion(&mut can3),
+            Some((Mailbox::Mailbox0, TransmitResult::Error))
+        );
+        assert_eq!(completion(&mut can3), None);
+
+        // The outcome does not depend on the configuration at the time it is polled.
+        can3.transmit(&frame(1)).unwrap();
+        assert!(!sim.step());
+        can3.modify_config().set_automatic_retransmit(true).enable();
+        assert_eq!(
+            completion(&mut can3),
+            Some((Mailbox::Mailbox0, TransmitResult::Error))
+        );
+        can3.modify_config()
+            .set_automatic_retransmit(false)
+            .enable();
+
+        // CAN1 wins arbitration.
+        let mut can1 = one_shot(p.can1);
+        can1.transmit(&frame(1)).unwrap();
+        can3.transmit(&frame(2)).unwrap();
+        assert!(sim.step());
+        assert_eq!(
+            completion(&mut can1),
+            Some((Mailbox::Mailbox0, TransmitResult::Transmitted))
+        );
+        let completions = can3.take_completions().collect::<Vec<_>>();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].result(), TransmitResult::ArbitrationLost);
+        assert_eq!(can3.take_completions().count(), 0);
+
+        // With automatic retransmission, failed attempts do not complete the request.
+        can3.modify_config().set_automatic_retransmit(true).enable();
+        can1.modify_config().set_silent(true).enable();
+        let status = can3.transmit(&frame(3)).unwrap();
+        assert!(!sim.step());
+        assert!(can3.registers().tsr.read().terr0().bit_is_set());
+        assert_eq!(completion(&mut can3), None);
+        assert!(can3.abort(status.mailbox()));
+        assert_eq!(
+            completion(&mut can3),
+            Some((status.mailbox(), TransmitResult::Aborted))
+        );
+        assert_eq!(completion(&mut can3), None);
+    }
+
+    #[test]
+    fn transmit_tokens() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+        let frame = |id| Frame::new_data(StandardId::new(id).unwrap(), []);
+
+        // The mailbox is reused before its completion is polled.
+        let first = can.transmit(&frame(1)).unwrap();
+        assert!(sim.step());
+        let second = can.transmit(&frame(1)).unwrap();
+        assert_eq!(first.mailbox(), second.mailbox());
+        assert!(first.token() < second.token());
+
+        let completion = can.poll_completion().unwrap();
+        assert_eq!(completion.token(), Some(first.token()));
+        assert_eq!(completion.result(), TransmitResult::Transmitted);
+        assert!(can.poll_completion().is_none());
+        assert!(sim.step());
+        let completion = can.poll_completion().unwrap();
+        assert_eq!(completion.token(), Some(second.token()));
+
+        // Dequeued and aborted frames report their tokens.
+        let tokens = [4, 3, 2].map(|id| can.transmit(&frame(id)).unwrap());
+        let status = can.transmit(&frame(0)).unwrap();
+        assert_eq!(status.dequeued_token(), Some(tokens[0].token()));
+        assert!(tokens
+            .iter()
+            .all(|pending| pending.dequeued_token().is_none() && pending.token() < status.token()));
+        assert!(can.abort(tokens[1].mailbox()));
+        let completions = can.take_completions().collect::<Vec<_>>();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].token(), Some(tokens[1].token()));
+        assert_eq!(completions[0].result(), TransmitResult::Aborted);
+    }
+
+    #[test]
+    fn lost_completions() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+        let frame = Frame::new_data(StandardId::new(1).unwrap(), []);
+
+        let mut tokens = Vec::new();
+        for _ in 0..10 {
+            tokens.push(can.transmit(&frame).unwrap().token());
+            assert!(sim.step());
+        }
+        assert_eq!(can.lost_completions(), 1);
+        let completions = can.take_completions().collect::<Vec<_>>();
+        assert_eq!(completions.len(), 9);
+        assert!(completions[..8]
+            .iter()
+            .zip(&tokens)
+            .all(|(completion, token)| completion.token() == Some(*token)));
+        assert_eq!(completions[8].token(), Some(tokens[9]));
+        assert!(can.poll_completion().is_none());
+    }
+
+    #[test]
+    fn pending_and_abort_all() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        let frames = [
+            Frame::new_data(ExtendedId::new(3 << 18 | 5).unwrap(), [1, 2, 3]),
+            Frame::new_remote(StandardId::new(2).unwrap(), 4),
+            Frame::new_data(StandardId::new(1).unwrap(), [0; 8]),
+        ];
+        let statuses = frames.each_ref().map(|frame| can.transmit(frame).unwrap());
+        let mailboxes = statuses.each_ref().map(|status| status.mailbox());
+        let pending = can.pending().collect::<Vec<_>>();
+        assert_eq!(pending.len(), 3);
+        for (mailbox, frame) in mailboxes.iter().zip(&frames) {
+            assert!(pending.contains(&(*mailbox, frame.clone())));
+        }
+
+        let aborted = can.abort_all().collect::<Vec<_>>();
+        assert_eq!(aborted.len(), 3);
+        for (status, frame) in statuses.iter().zip(&frames) {
+            assert!(aborted.contains(&(status.token(), frame.clone())));
+        }
+        assert!(can.is_transmitter_idle());
+        assert_eq!(can.pending().count(), 0);
+        assert_eq!(can.abort_all().count(), 0);
+        let completions = can.take_completions().collect::<Vec<_>>();
+        assert_eq!(completions.len(), 3);
+        for status in &statuses {
+            assert!(completions.iter().any(|completion| {
+                completion.mailbox() == status.mailbox()
+                    && completion.token() == Some(status.token())
+                    && completion.result() == TransmitResult::Aborted
+            }));
+        }
+        assert_eq!(sim.run(), 0);
+
+        // Aborted frames can be sent again later.
+        let frame = &aborted[0].1;
+        can.transmit(frame).unwrap();
+        assert_eq!(sim.run(), 1);
+        assert_eq!(&can.receive().unwrap(), frame);
+    }
+
+    #[test]
+    fn transmit_deadlines() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can3);
+        can.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        let frames = [3, 2, 1].map(|id| Frame::new_data(StandardId::new(id).unwrap(), [id as u8]));
+        let a = can
+            .transmit_with_deadline(&frames[0], Instant::from_ticks(10))
+            .unwrap()
+            .token();
+        let b = can
+            .transmit_with_deadline(&frames[1], Instant::from_ticks(20))
+            .unwrap()
+            .token();
+        can.transmit(&frames[2]).unwrap();
+
+        assert_eq!(can.abort_expired(Instant::from_ticks(5)).count(), 0);
+        let aborted = can
+            .abort_expired(Instant::from_ticks(10))
+            .collect::<Vec<_>>();
+        assert_eq!(aborted, [(a, frames[0].clone())]);
+        let aborted = can
+            .abort_expired(Instant::from_ticks(100))
+            .collect::<Vec<_>>();
+        assert_eq!(aborted, [(b, frames[1].clone())]);
+        assert_eq!(can.abort_expired(Instant::from_ticks(1000)).count(), 0);
+        assert_eq!(can.pending().count(), 1);
+
+        assert_eq!(sim.run(), 1);
+        assert_eq!(can.receive().unwrap(), frames[2]);
+
+        // Transmitted frames are not aborted, even if their deadline has passed.
+        can.transmit_with_deadline(&frames[0], Instant::from_ticks(10))
+            .unwrap();
+        assert_eq!(sim.run(), 1);
+        assert_eq!(can.abort_expired(Instant::from_ticks(100)).count(), 0);
+
+        // Deadlines are compared with wrapping arithmetic.
+        can.transmit_with_deadline(&frames[0], Instant::from_ticks(u32::MAX - 1))
+            .unwrap();
+        assert_eq!(
+            can.abort_expired(Instant::from_ticks(u32::MAX - 2)).count(),
+            0
+        );
+        assert_eq!(can.abort_expired(Instant::from_ticks(3)).count(), 1);
+        assert_eq!(sim.run(), 0);
+    }
 }
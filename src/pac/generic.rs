@@ -23,7 +23,7 @@ pub trait ResetValue {
 
 ///This structure provides volatile access to register
 pub struct Reg<U, REG> {
-    register: vcell::VolatileCell<U>,
+    register: super::VolatileCell<U>,
     _marker: marker::PhantomData<REG>,
 }
 
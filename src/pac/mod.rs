@@ -7,3 +7,11 @@ use core::ops::Deref;
 #[doc = "Controller area network"]
 pub mod can;
 pub mod generic;
+
+// `generic.rs` is generated by svd2rust. After regenerating it, change the type of the `register`
+// field of `Reg` from `vcell::VolatileCell<U>` to `super::VolatileCell<U>`, so that register
+// writes reach the simulated peripherals of the `sim` module.
+#[cfg(any(test, feature = "sim"))]
+use crate::sim::VolatileCell;
+#[cfg(not(any(test, feature = "sim")))]
+use vcell::VolatileCell;
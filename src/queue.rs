@@ -0,0 +1,583 @@
+//! Software queues that extend the hardware mailboxes.
+//!
+//! Each receive FIFO of the peripheral holds only three frames. [`RxQueue`] is a fixed-capacity
+//! ring buffer that the receive interrupt handlers drain the FIFOs into, so that bursts of frames
+//! are not lost while the application is busy.
+//!
+//! There are only three transmit mailboxes. [`TxQueue`] holds further frames in order of their
+//! priority, and moves them into the mailboxes as they become free.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::{Envelope, Fifo, Frame, Instance, Rx0, Rx1, Tx};
+
+/// A single-producer, single-consumer queue of received frames.
+///
+/// The queue is [split](RxQueue::split) into an [`RxProducer`], used by the RX0 and RX1 interrupt
+/// handlers to move frames out of the receive FIFOs, and an [`RxConsumer`], used by application
+/// code to take them out of the queue. Both sides operate without locks, so they can be used
+/// from different execution contexts.
+///
+/// The queue holds up to `N` frames. Frames that arrive while it is full are discarded, and
+/// counted per FIFO alongside overruns of the hardware FIFOs.
+///
+/// To use the producer from an interrupt handler, the queue usually has to be stored in a
+/// `static` location, so that the halves borrow it for `'static`.
+pub struct RxQueue<const N: usize> {
+    buffer: [UnsafeCell<MaybeUninit<Envelope>>; N],
+    /// Position of the next frame to dequeue, modulo `2 * N`.
+    read: AtomicUsize,
+    /// Position of the next frame to enqueue, modulo `2 * N`.
+    write: AtomicUsize,
+    hardware_overruns: [AtomicU32; 2],
+    queue_overruns: [AtomicU32; 2],
+}
+
+// Safety: The producer only writes to slots the consumer does not read, and vice versa.
+unsafe impl<const N: usize> Sync for RxQueue<N> {}
+
+impl<const N: usize> RxQueue<N> {
+    #[allow(clippy::declare_interior_mutable_const)] // only used to initialize `buffer`
+    const EMPTY: UnsafeCell<MaybeUninit<Envelope>> = UnsafeCell::new(MaybeUninit::uninit());
+
+    /// Creates an empty queue.
+    ///
+    /// Panics if `N` is 0.
+    pub const fn new() -> Self {
+        assert!(N > 0 && N <= usize::MAX / 2, "invalid queue capacity");
+        Self {
+            buffer: [Self::EMPTY; N],
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            hardware_overruns: [AtomicU32::new(0), AtomicU32::new(0)],
+            queue_overruns: [AtomicU32::new(0), AtomicU32::new(0)],
+        }
+    }
+
+    /// Splits the queue into its producer and consumer halves.
+    pub fn split(&mut self) -> (RxProducer<'_, N>, RxConsumer<'_, N>) {
+        let queue = &*self;
+        (RxProducer { queue }, RxConsumer { queue })
+    }
+
+    /// Returns the maximum number of frames the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    fn len(&self) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        let write = self.write.load(Ordering::Acquire);
+        (write + 2 * N - read) % (2 * N)
+    }
+
+    fn hardware_overruns(&self, fifo: Fifo) -> u32 {
+        self.hardware_overruns[fifo as usize].load(Ordering::Relaxed)
+    }
+
+    fn queue_overruns(&self, fifo: Fifo) -> u32 {
+        self.queue_overruns[fifo as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl<const N: usize> Default for RxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Advances a position in the queue by one.
+fn next<const N: usize>(pos: usize) -> usize {
+    if pos + 1 == 2 * N {
+        0
+    } else {
+        pos + 1
+    }
+}
+
+/// Increments a counter that is only ever written by a single context.
+///
+/// Not all targets support atomic read-modify-write operations.
+fn increment(counter: &AtomicU32) {
+    let value = counter.load(Ordering::Relaxed);
+    counter.store(value.wrapping_add(1), Ordering::Relaxed);
+}
+
+/// The producer half of an [`RxQueue`], which fills it from the receive FIFOs.
+pub struct RxProducer<'a, const N: usize> {
+    queue: &'a RxQueue<N>,
+}
+
+impl<const N: usize> RxProducer<'_, N> {
+    /// Moves all frames from receive FIFO 0 into the queue.
+    ///
+    /// This is meant to be called from the RX0 interrupt handler. Frames that do not fit into the
+    /// queue are discarded, so that the FIFO is always emptied and the interrupt does not fire
+    /// again right away.
+    ///
+    /// Returns the number of frames added to the queue.
+    pub fn drain_rx0<I: Instance>(&mut self, rx: &mut Rx0<I>) -> usize {
+        self.drain(Fifo::Fifo0, || rx.receive_with_meta())
+    }
+
+    /// Moves all frames from receive FIFO 1 into the queue.
+    ///
+    /// This is meant to be called from the RX1 interrupt handler. Frames that do not fit into the
+    /// queue are discarded, so that the FIFO is always emptied and the interrupt does not fire
+    /// again right away.
+    ///
+    /// Returns the number of frames added to the queue.
+    pub fn drain_rx1<I: Instance>(&mut self, rx: &mut Rx1<I>) -> usize {
+        self.drain(Fifo::Fifo1, || rx.receive_with_meta())
+    }
+
+    /// Returns `true` if the queue can not hold any more frames.
+    pub fn is_full(&self) -> bool {
+        self.queue.len() == N
+    }
+
+    fn drain(
+        &mut self,
+        fifo: Fifo,
+        mut receive: impl FnMut() -> nb::Result<Envelope, crate::OverrunError>,
+    ) -> usize {
+        let mut enqueued = 0;
+        loop {
+            match receive() {
+                Ok(envelope) => {
+                    if self.enqueue(envelope) {
+                        enqueued += 1;
+                    } else {
+                        increment(&self.queue.queue_overruns[fifo as usize]);
+                    }
+                }
+                Err(nb::Error::Other(_)) => {
+                    increment(&self.queue.hardware_overruns[fifo as usize]);
+                }
+                Err(nb::Error::WouldBlock) => return enqueued,
+            }
+        }
+    }
+
+    fn enqueue(&mut self, envelope: Envelope) -> bool {
+        let queue = self.queue;
+        let write = queue.write.load(Ordering::Relaxed);
+        let read = queue.read.load(Ordering::Acquire);
+        if (write + 2 * N - read) % (2 * N) == N {
+            return false;
+        }
+
+        // Safety: The slot is not part of the queue, so the consumer does not access it.
+        unsafe { (*queue.buffer[write % N].get()).write(envelope) };
+        queue.write.store(next::<N>(write), Ordering::Release);
+        true
+    }
+}
+
+/// The consumer half of an [`RxQueue`], which takes received frames out of it.
+pub struct RxConsumer<'a, const N: usize> {
+    queue: &'a RxQueue<N>,
+}
+
+impl<const N: usize> RxConsumer<'_, N> {
+    /// Takes the oldest frame out of the queue.
+    ///
+    /// Frames from both FIFOs are returned in the order they were moved into the queue.
+    pub fn dequeue(&mut self) -> Option<Envelope> {
+        let queue = self.queue;
+        let read = queue.read.load(Ordering::Relaxed);
+        let write = queue.write.load(Ordering::Acquire);
+        if read == write {
+            return None;
+        }
+
+        // Safety: The slot is part of the queue, so the producer has initialized it and does not
+        // access it.
+        let envelope = unsafe { (*queue.buffer[read % N].get()).assume_init_read() };
+        queue.read.store(next::<N>(read), Ordering::Release);
+        Some(envelope)
+    }
+
+    /// Returns the number of frames in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if there are no frames in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of overruns of a receive FIFO.
+    ///
+    /// An overrun happens when a frame arrives while the hardware FIFO is full, which means that
+    /// the interrupt handler did not drain it in time. At least one frame is lost per overrun. The
+    /// counter wraps around on overflow.
+    pub fn hardware_overruns(&self, fifo: Fifo) -> u32 {
+        self.queue.hardware_overruns(fifo)
+    }
+
+    /// Returns the number of frames from a receive FIFO that were discarded because the queue was
+    /// full.
+    ///
+    /// The counter wraps around on overflow.
+    pub fn queue_overruns(&self, fifo: Fifo) -> u32 {
+        self.queue.queue_overruns(fifo)
+    }
+}
+
+/// A fixed-capacity queue of frames waiting for a free transmit mailbox.
+///
+/// Frames are kept in order of their [priority](crate::FramePriority), and frames of equal
+/// priority in the order they were pushed. [`TxQueue::refill`] moves them into the transmit
+/// mailboxes with [`Tx::transmit`], and takes back any lower-priority frame that
+/// [`Tx::transmit`] dequeues to make room for a higher-priority one.
+///
+/// Since [`Tx::transmit`] only accepts frames that do not cause a priority inversion,
+/// [`refill`](TxQueue::refill) has to be called again whenever a mailbox becomes empty. This is
+/// usually done from the interrupt handler for [`Interrupt::TransmitMailboxEmpty`], which also has
+/// to acknowledge the interrupt by taking the finished requests with [`Tx::take_completions`] or
+/// [`Tx::poll_completion`]. After pushing frames, call [`refill`](TxQueue::refill) once to start
+/// transmission while the mailboxes are idle.
+///
+/// Unlike [`RxQueue`], this queue is not split into halves. When it is used from thread and
+/// interrupt context, it has to be protected by a lock, for example a [`critical-section`]
+/// mutex, together with the [`Tx`] instance.
+///
+/// [`Interrupt::TransmitMailboxEmpty`]: crate::Interrupt::TransmitMailboxEmpty
+/// [`critical-section`]: https://docs.rs/critical-section
+pub struct TxQueue<const N: usize> {
+    /// The queued frames in `frames[..len]`, in transmission order.
+    frames: [Option<Frame>; N],
+    len: usize,
+    dropped: u32,
+    requeued: u32,
+}
+
+impl<const N: usize> TxQueue<N> {
+    const EMPTY: Option<Frame> = None;
+
+    /// Creates an empty queue.
+    pub const fn new() -> Self {
+        Self {
+            frames: [Self::EMPTY; N],
+            len: 0,
+            dropped: 0,
+            requeued: 0,
+        }
+    }
+
+    /// Adds a frame to the queue.
+    ///
+    /// If the queue is full, the frame with the lowest priority is dropped, which may be `frame`
+    /// itself, and returned as `Err`. Among frames of equal priority, the most recently pushed one
+    /// is dropped.
+    pub fn push(&mut self, frame: Frame) -> Result<(), Frame> {
+        let priority = frame.priority();
+        let pos = self.frames[..self.len]
+            .iter()
+            .position(|queued| queued.as_ref().unwrap().priority() < priority)
+            .unwrap_or(self.len);
+        self.insert(pos, frame)
+    }
+
+    /// Moves frames from the queue into free transmit mailboxes.
+    ///
+    /// Frames that [`Tx::transmit`] dequeues from a mailbox are put back into the queue, ahead of
+    /// queued frames of equal priority.
+    ///
+    /// Returns the number of frames moved into mailboxes.
+    pub fn refill<I: Instance>(&mut self, tx: &mut Tx<I>) -> usize {
+        let mut transmitted = 0;
+        while self.len > 0 {
+            let frame = self.frames[0].as_ref().unwrap();
+            match tx.transmit(frame) {
+                Ok(status) => {
+                    self.remove_first();
+                    transmitted += 1;
+                    if let Some(dequeued) = status.dequeued_frame() {
+                        // Removing the transmitted frame made room for the dequeued one.
+                        self.requeued = self.requeued.wrapping_add(1);
+                        let priority = dequeued.priority();
+                        let pos = self.frames[..self.len]
+                            .iter()
+                            .position(|queued| queued.as_ref().unwrap().priority() <= priority)
+                            .unwrap_or(self.len);
+                        let _ = self.insert(pos, dequeued.clone());
+                    }
+                }
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(infallible)) => match infallible {},
+            }
+        }
+        transmitted
+    }
+
+    /// Returns the number of frames in the queue.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no frames in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the queue can not hold any more frames.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns the maximum number of frames the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of frames dropped because the queue was full.
+    ///
+    /// The counter wraps around on overflow.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the number of frames that were dequeued from a transmit mailbox in favor of a
+    /// higher-priority frame, and put back into the queue.
+    ///
+    /// The counter wraps around on overflow.
+    pub fn requeued(&self) -> u32 {
+        self.requeued
+    }
+
+    /// Inserts `frame` at `pos`, dropping the last frame if the queue is full.
+    fn insert(&mut self, pos: usize, frame: Frame) -> Result<(), Frame> {
+        if pos == N {
+            self.dropped = self.dropped.wrapping_add(1);
+            return Err(frame);
+        }
+
+        let dropped = if self.len == N {
+            self.dropped = self.dropped.wrapping_add(1);
+            self.frames[N - 1].take()
+        } else {
+            self.len += 1;
+            None
+        };
+        self.frames[pos..self.len].rotate_right(1);
+        self.frames[pos] = Some(frame);
+        dropped.map_or(Ok(()), Err)
+    }
+
+    fn remove_first(&mut self) {
+        self.frames[..self.len].rotate_left(1);
+        self.len -= 1;
+        self.frames[self.len] = None;
+    }
+}
+
+impl<const N: usize> Default for TxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Mask32;
+    use crate::sim::{Sim, SimCan3};
+    use crate::{Can, StandardId, TransmitResult};
+
+    extern crate std;
+    use std::vec::Vec;
+
+    fn setup(p: SimCan3) -> Can<SimCan3> {
+        let mut can = Can::builder(p).set_loopback(true).set_silent(true).enable();
+        // Odd IDs go to FIFO 1, the lower bank takes precedence.
+        can.modify_filters()
+            .enable_bank(1, Fifo::Fifo0, Mask32::accept_all())
+            .enable_bank(
+                0,
+                Fifo::Fifo1,
+                Mask32::frames_with_std_id(
+                    StandardId::new(1).unwrap(),
+                    StandardId::new(1).unwrap(),
+                ),
+            );
+        can
+    }
+
+    fn frame(id: u16) -> Frame {
+        Frame::new_data(StandardId::new(id).unwrap(), [id as u8])
+    }
+
+    #[test]
+    fn rx_queue_order() {
+        let (mut sim, p) = Sim::new();
+        let mut can = setup(p.can3);
+        let mut queue = RxQueue::<8>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        assert_eq!(consumer.dequeue(), None);
+        for round in 0..4 {
+            // More frames than fit into a hardware FIFO.
+            for id in 0..6 {
+                can.transmit(&frame(round * 6 + id)).unwrap();
+                sim.step();
+                if id % 3 == 2 {
+                    let (_, rx0, rx1) = can.split_by_ref();
+                    producer.drain_rx0(rx0);
+                    producer.drain_rx1(rx1);
+                }
+            }
+            assert_eq!(consumer.len(), 6);
+
+            let mut received = [None; 6];
+            while let Some(envelope) = consumer.dequeue() {
+                let id = envelope.frame().data().unwrap()[0] - (round * 6) as u8;
+                let expected = if id % 2 == 1 {
+                    Fifo::Fifo1
+                } else {
+                    Fifo::Fifo0
+                };
+                assert_eq!(envelope.fifo(), expected);
+                assert!(received[usize::from(id)].replace(envelope.fifo()).is_none());
+            }
+            assert!(received.iter().all(Option::is_some));
+        }
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.hardware_overruns(Fifo::Fifo0), 0);
+        assert_eq!(consumer.queue_overruns(Fifo::Fifo0), 0);
+    }
+
+    #[test]
+    fn rx_queue_overruns() {
+        let (mut sim, p) = Sim::new();
+        let mut can = setup(p.can3);
+        let mut queue = RxQueue::<2>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        // 4 frames overrun the hardware FIFO.
+        for id in [0, 2, 4, 6] {
+            can.transmit(&frame(id)).unwrap();
+            sim.step();
+        }
+        assert_eq!(producer.drain_rx0(can.rx0()), 2);
+        assert!(producer.is_full());
+        assert_eq!(consumer.hardware_overruns(Fifo::Fifo0), 1);
+        assert_eq!(consumer.queue_overruns(Fifo::Fifo0), 1);
+
+        can.transmit(&frame(1)).unwrap();
+        sim.step();
+        assert_eq!(producer.drain_rx1(can.rx1()), 0);
+        assert_eq!(consumer.queue_overruns(Fifo::Fifo1), 1);
+        assert_eq!(consumer.hardware_overruns(Fifo::Fifo1), 0);
+
+        assert_eq!(consumer.dequeue().unwrap().frame(), &frame(0));
+        assert_eq!(consumer.dequeue().unwrap().frame(), &frame(2));
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    /// Transmits the frames of `queue` and returns the order in which they were received.
+    fn transmit_all<const N: usize>(
+        sim: &mut Sim,
+        can: &mut Can<SimCan3>,
+        queue: &mut TxQueue<N>,
+    ) -> Vec<Frame> {
+        let mut received = Vec::new();
+        loop {
+            let (tx, _, _) = can.split_by_ref();
+            queue.refill(tx);
+            if !sim.step() {
+                return received;
+            }
+            // What the interrupt handler would do.
+            assert!(tx
+                .take_completions()
+                .all(|completion| completion.result() == TransmitResult::Transmitted));
+            while let Ok(frame) = can.receive() {
+                received.push(frame);
+            }
+        }
+    }
+
+    fn tagged(id: u16, tag: u8) -> Frame {
+        Frame::new_data(StandardId::new(id).unwrap(), [tag])
+    }
+
+    #[test]
+    fn tx_queue_order() {
+        let (mut sim, p) = Sim::new();
+        let mut can = setup(p.can3);
+        let mut queue = TxQueue::<8>::new();
+
+        let frames = [
+            tagged(7, 0),
+            tagged(3, 0),
+            tagged(7, 1),
+            Frame::new_remote(StandardId::new(3).unwrap(), 0),
+            tagged(1, 0),
+            tagged(7, 2),
+        ];
+        for frame in &frames {
+            queue.push(frame.clone()).unwrap();
+        }
+        assert_eq!(queue.len(), 6);
+
+        let received = transmit_all(&mut sim, &mut can, &mut queue);
+        let expected = [
+            &frames[4], &frames[1], &frames[3], &frames[0], &frames[2], &frames[5],
+        ];
+        assert_eq!(received.iter().collect::<Vec<_>>(), expected);
+        assert!(queue.is_empty());
+        assert_eq!(queue.requeued(), 0);
+    }
+
+    #[test]
+    fn tx_queue_takes_back_preempted_frames() {
+        let (mut sim, p) = Sim::new();
+        let mut can = setup(p.can3);
+        let mut queue = TxQueue::<8>::new();
+
+        queue.push(tagged(7, 0)).unwrap();
+        assert_eq!(queue.refill(can.split_by_ref().0), 1);
+        queue.push(tagged(7, 1)).unwrap();
+        queue.push(tagged(7, 2)).unwrap();
+        assert_eq!(queue.refill(can.split_by_ref().0), 0);
+
+        // Frames of increasing priority take the free mailboxes, then replace the pending frame.
+        for id in [4, 3, 2] {
+            queue.push(tagged(id, 0)).unwrap();
+            assert_eq!(queue.refill(can.split_by_ref().0), 1);
+        }
+        assert_eq!(queue.requeued(), 1);
+        assert_eq!(queue.len(), 3);
+
+        let received = transmit_all(&mut sim, &mut can, &mut queue);
+        let expected = [
+            tagged(2, 0),
+            tagged(3, 0),
+            tagged(4, 0),
+            tagged(7, 0),
+            tagged(7, 1),
+            tagged(7, 2),
+        ];
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn tx_queue_full() {
+        let mut queue = TxQueue::<2>::new();
+        queue.push(tagged(5, 0)).unwrap();
+        queue.push(tagged(6, 0)).unwrap();
+        assert!(queue.is_full());
+
+        assert_eq!(queue.push(tagged(6, 1)), Err(tagged(6, 1)));
+        assert_eq!(queue.push(tagged(1, 0)), Err(tagged(6, 0)));
+        assert_eq!(queue.push(tagged(5, 1)), Err(tagged(5, 1)));
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.frames, [Some(tagged(1, 0)), Some(tagged(5, 0))]);
+    }
+}
@@ -0,0 +1,1711 @@
+//! Simulated bxCAN peripherals for testing without hardware.
+//!
+//! This module provides RAM-backed register blocks together with a behavioural model of the
+//! bxCAN peripheral, so that code using [`Can`], [`Tx`], [`Rx0`], [`Rx1`] and the filter API can
+//! be tested on the host.
+//!
+//! Three simulated peripherals are available:
+//!
+//! * [`SimCan1`] is a master instance owning 28 filter banks, like CAN1 on connectivity-line
+//!   devices.
+//! * [`SimCan2`] is the corresponding slave instance, using the filter banks assigned to it via
+//!   [`MasterFilters::set_split`].
+//! * [`SimCan3`] is a standalone instance with 14 filter banks of its own.
+//!
+//! The model reacts to register writes made by the driver: it performs the initialization and
+//! sleep mode handshakes, manages the three transmit mailboxes (including transmit and abort
+//! requests), and the two receive FIFOs with a depth of 3 frames each (including overrun). Frames
+//! are not transmitted by themselves, instead [`Sim::step`] advances the simulation by one frame.
+//! Received frames pass through the configured filters, using the same acceptance and filter
+//! match index rules as the hardware.
+//!
+//! # Virtual bus
+//!
+//! All simulated peripherals are connected to the same virtual CAN bus. Peripherals in silent
+//! loopback mode are disconnected from it and only receive their own frames. Otherwise, in each
+//! step:
+//!
+//! * All peripherals with a pending frame arbitrate for the bus. The frame with the highest
+//!   priority (see [`FramePriority`]) wins, the other transmitters lose arbitration and retry
+//!   later (unless automatic retransmission is disabled).
+//! * If several peripherals send frames with the same identifier but different contents, the one
+//!   that sends a recessive bit first detects a bit error. As long as it is error active, its
+//!   error flag destroys the frame for everyone.
+//! * The frame is acknowledged by every other enabled peripheral that is not in silent or
+//!   loopback mode, and delivered to all of them except those in loopback mode. Peripherals in
+//!   loopback mode ignore missing acknowledgements and receive their own frames. A frame sent
+//!   while no other node is listening causes an acknowledgement error.
+//! * The transmit and receive error counters in ESR follow the rules of ISO 11898-1, including
+//!   error passive and bus-off state. Bus-off recovery counts 8 of the required 128 sequences of
+//!   11 recessive bits per step.
+//!
+//! # Fault injection
+//!
+//! [`Sim::inject_fault`] and [`Sim::inject_fault_for`] corrupt frames on the virtual bus, either
+//! with a specific kind of error or by inverting a single bit on the wire (see [`Fault`]). The
+//! peripherals detect the error like the hardware would: they record it in the last error code,
+//! update their error counters and raise the error interrupt flag if the corresponding interrupt
+//! is enabled.
+//!
+//! All simulated peripherals share global state, so only one [`Sim`] can exist at a time. Creating
+//! one waits until the previous one has been dropped, which serializes tests running in parallel.
+//!
+//! # Example
+//!
+//! ```
+//! use bxcan::filter::Mask32;
+//! use bxcan::sim::Sim;
+//! use bxcan::{Can, Fifo, Frame, StandardId};
+//!
+//! let (mut sim, p) = Sim::new();
+//! let mut can = Can::builder(p.can1).set_loopback(true).enable();
+//! can.modify_filters()
+//!     .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+//!
+//! let frame = Frame::new_data(StandardId::new(0x123).unwrap(), [1, 2, 3]);
+//! can.transmit(&frame).unwrap();
+//! assert!(can.receive().is_err());
+//!
+//! sim.step();
+//! assert_eq!(can.receive().unwrap(), frame);
+//! ```
+//!
+//! [`Can`]: crate::Can
+//! [`Tx`]: crate::Tx
+//! [`Rx0`]: crate::Rx0
+//! [`Rx1`]: crate::Rx1
+//! [`MasterFilters::set_split`]: crate::filter::MasterFilters::set_split
+//! [`FramePriority`]: crate::FramePriority
+
+extern crate std;
+
+use core::cmp::Ordering;
+use core::mem::size_of;
+use core::ptr::addr_of_mut;
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::vec::Vec;
+
+use crate::pac::can::RegisterBlock;
+use crate::{FilterOwner, Id, IdReg, Instance, MasterInstance};
+
+/// Number of simulated peripherals.
+const NODES: usize = 3;
+
+const WORDS: usize = size_of::<RegisterBlock>() / 4;
+
+/// Number of frames each receive FIFO can hold.
+const FIFO_DEPTH: usize = 3;
+
+static mut MEMORY: [[u32; WORDS]; NODES] = [[0; WORDS]; NODES];
+
+/// Serializes users of the simulation.
+static LOCK: Mutex<()> = Mutex::new(());
+
+static MODEL: Mutex<Model> = Mutex::new(Model::new());
+
+const MCR_INRQ: u32 = 1 << 0;
+const MCR_SLEEP: u32 = 1 << 1;
+const MCR_TXFP: u32 = 1 << 2;
+const MCR_RFLM: u32 = 1 << 3;
+const MCR_NART: u32 = 1 << 4;
+const MCR_AWUM: u32 = 1 << 5;
+const MCR_ABOM: u32 = 1 << 6;
+const MCR_TTCM: u32 = 1 << 7;
+const MCR_RESET: u32 = 1 << 15;
+const MCR_RESET_VALUE: u32 = 0x0001_0002;
+
+const MSR_INAK: u32 = 1 << 0;
+const MSR_SLAK: u32 = 1 << 1;
+const MSR_ERRI: u32 = 1 << 2;
+const MSR_WKUI: u32 = 1 << 3;
+const MSR_SLAKI: u32 = 1 << 4;
+/// Write-1-to-clear interrupt flags (ERRI, WKUI, SLAKI).
+const MSR_W1C: u32 = 0b111 << 2;
+const MSR_RESET_VALUE: u32 = 0x0000_0C02;
+
+const TSR_CODE_SHIFT: u32 = 24;
+const TSR_TME_SHIFT: u32 = 26;
+const TSR_LOW_SHIFT: u32 = 29;
+const TSR_RESET_VALUE: u32 = 0x1C00_0000;
+
+const RFR_FMP: u32 = 0b11;
+const RFR_FULL: u32 = 1 << 3;
+const RFR_FOVR: u32 = 1 << 4;
+const RFR_RFOM: u32 = 1 << 5;
+
+const ESR_EWGF: u32 = 1 << 0;
+const ESR_EPVF: u32 = 1 << 1;
+const ESR_BOFF: u32 = 1 << 2;
+const ESR_LEC: u32 = 0b111 << 4;
+const LEC_STUFF: u32 = 1 << 4;
+const LEC_FORM: u32 = 2 << 4;
+const LEC_ACKNOWLEDGEMENT: u32 = 3 << 4;
+const LEC_BIT_RECESSIVE: u32 = 4 << 4;
+const LEC_BIT_DOMINANT: u32 = 5 << 4;
+const LEC_CRC: u32 = 6 << 4;
+
+/// Error warning, error passive and bus-off interrupt enables, in ESR flag order.
+const IER_ERROR_SHIFT: u32 = 8;
+const IER_LECIE: u32 = 1 << 11;
+
+/// Number of sequences of 11 recessive bits required for bus-off recovery.
+const RECOVERY_SEQUENCES: u16 = 128;
+/// Number of recovery sequences counted per step.
+const RECOVERY_SEQUENCES_PER_STEP: u16 = 8;
+
+const BTR_LBKM: u32 = 1 << 30;
+const BTR_SILM: u32 = 1 << 31;
+const BTR_RESET_VALUE: u32 = 0x0123_0000;
+
+const TIR_TXRQ: u32 = 1 << 0;
+const TDTR_TGT: u32 = 1 << 8;
+const TDTR_DLC: u32 = 0xF;
+
+const FMR_FINIT: u32 = 1 << 0;
+const FMR_RESET_VALUE: u32 = 0x2A1C_0E01;
+
+const fn rqcp(idx: usize) -> u32 {
+    1 << (8 * idx)
+}
+
+const fn txok(idx: usize) -> u32 {
+    2 << (8 * idx)
+}
+
+const fn alst(idx: usize) -> u32 {
+    4 << (8 * idx)
+}
+
+const fn terr(idx: usize) -> u32 {
+    8 << (8 * idx)
+}
+
+const fn abrq(idx: usize) -> u32 {
+    0x80 << (8 * idx)
+}
+
+/// Returns the register block of simulated peripheral `node`.
+const fn node_registers(node: usize) -> *mut RegisterBlock {
+    (addr_of_mut!(MEMORY) as *mut RegisterBlock).wrapping_add(node)
+}
+
+fn regs(node: usize) -> &'static RegisterBlock {
+    // Safety: `node_registers` points into `MEMORY`, which is valid for the whole program.
+    unsafe { &*node_registers(node) }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panicking test must not break all following ones.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn addr<T>(reg: &T) -> usize {
+    reg as *const T as usize
+}
+
+/// Reads a register without going through the PAC.
+fn get<T>(reg: &T) -> u32 {
+    unsafe { (reg as *const T as *const u32).read_volatile() }
+}
+
+/// Writes a register without going through the PAC, and thus without notifying the model.
+fn set<T>(reg: &T, value: u32) {
+    unsafe { (reg as *const T as *mut u32).write_volatile(value) }
+}
+
+/// Returns the identifier of the frame in a transmit mailbox, ordered by priority.
+fn frame_id(node: usize, idx: usize) -> IdReg {
+    IdReg::from_register(get(&regs(node).tx[idx].tir))
+}
+
+/// Returns the DLC and data of the frame in a transmit mailbox, in the order they are sent.
+///
+/// Lower values win, since dominant bits are 0.
+fn frame_contents(node: usize, idx: usize) -> (u32, [u8; 8]) {
+    let mb = &regs(node).tx[idx];
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&get(&mb.tdlr).to_le_bytes());
+    data[4..].copy_from_slice(&get(&mb.tdhr).to_le_bytes());
+    (get(&mb.tdtr) & TDTR_DLC, data)
+}
+
+/// The cell type backing the PAC registers, which notifies the model about register writes.
+#[repr(transparent)]
+pub(crate) struct VolatileCell<T> {
+    value: vcell::VolatileCell<T>,
+}
+
+impl<T: Copy> VolatileCell<T> {
+    #[inline(always)]
+    pub(crate) fn get(&self) -> T {
+        self.value.get()
+    }
+
+    #[inline(always)]
+    pub(crate) fn set(&self, value: T) {
+        let old = self.value.get();
+        self.value.set(value);
+        register_written(self as *const Self as *const (), old);
+    }
+}
+
+/// Called after a register has been written.
+fn register_written<U: Copy>(reg: *const (), old: U) {
+    let offset = (reg as usize).wrapping_sub(node_registers(0) as usize);
+    if offset >= NODES * size_of::<RegisterBlock>() || size_of::<U>() != 4 {
+        return;
+    }
+
+    // Safety: All bxCAN registers are 32 bits wide, as checked above.
+    let old = unsafe { core::mem::transmute_copy::<U, u32>(&old) };
+    let node = offset / size_of::<RegisterBlock>();
+    lock(&MODEL).register_written(node, reg as usize, old);
+}
+
+/// The simulated master peripheral, owning 28 filter banks shared with [`SimCan2`].
+pub struct SimCan1 {
+    _private: (),
+}
+
+unsafe impl Instance for SimCan1 {
+    const REGISTERS: *mut RegisterBlock = node_registers(0);
+}
+
+unsafe impl FilterOwner for SimCan1 {
+    const NUM_FILTER_BANKS: u8 = 28;
+}
+
+unsafe impl MasterInstance for SimCan1 {}
+
+/// The simulated slave peripheral of [`SimCan1`].
+pub struct SimCan2 {
+    _private: (),
+}
+
+unsafe impl Instance for SimCan2 {
+    const REGISTERS: *mut RegisterBlock = node_registers(1);
+}
+
+/// A simulated standalone peripheral, owning 14 filter banks.
+pub struct SimCan3 {
+    _private: (),
+}
+
+unsafe impl Instance for SimCan3 {
+    const REGISTERS: *mut RegisterBlock = node_registers(2);
+}
+
+unsafe impl FilterOwner for SimCan3 {
+    const NUM_FILTER_BANKS: u8 = 14;
+}
+
+/// The simulated peripherals, handed out by [`Sim::new`].
+pub struct SimPeripherals {
+    /// The master peripheral.
+    pub can1: SimCan1,
+    /// The slave peripheral.
+    pub can2: SimCan2,
+    /// The standalone peripheral.
+    pub can3: SimCan3,
+}
+
+/// Handle to the simulation.
+///
+/// This grants exclusive access to the simulated peripherals while it exists.
+pub struct Sim {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Sim {
+    /// Resets all simulated peripherals and takes control of them.
+    ///
+    /// If another `Sim` exists, this blocks until it is dropped.
+    pub fn new() -> (Self, SimPeripherals) {
+        let guard = lock(&LOCK);
+        lock(&MODEL).reset();
+
+        let peripherals = SimPeripherals {
+            can1: SimCan1 { _private: () },
+            can2: SimCan2 { _private: () },
+            can3: SimCan3 { _private: () },
+        };
+        (Self { _lock: guard }, peripherals)
+    }
+
+    /// Advances the simulation by one frame.
+    ///
+    /// Every enabled peripheral in silent loopback mode transmits its next pending frame to
+    /// itself, and one frame is transmitted on the virtual bus (see the [module
+    /// documentation](self) for details). Afterwards, the timer advances by one tick.
+    ///
+    /// Returns `true` if any frame was transmitted successfully.
+    pub fn step(&mut self) -> bool {
+        lock(&MODEL).step()
+    }
+
+    /// Steps the simulation until no more frames are transmitted successfully.
+    ///
+    /// Returns the number of steps that transmitted frames.
+    pub fn run(&mut self) -> usize {
+        let mut steps = 0;
+        while self.step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Returns the current value of the 16-bit timer used for timestamps.
+    pub fn time(&self) -> u16 {
+        lock(&MODEL).time
+    }
+
+    /// Advances the timer used for timestamps by `ticks`.
+    pub fn advance_time(&mut self, ticks: u16) {
+        let mut model = lock(&MODEL);
+        model.time = model.time.wrapping_add(ticks);
+    }
+
+    /// Injects `fault` into the next frame transmitted on the virtual bus.
+    ///
+    /// Faults are applied in the order they were injected, one per frame. Frames transmitted in
+    /// silent loopback mode never leave their peripheral and are not affected.
+    pub fn inject_fault(&mut self, fault: Fault) {
+        lock(&MODEL).faults.push_back((None, fault));
+    }
+
+    /// Injects `fault` into the next frame with identifier `id` transmitted on the virtual bus.
+    pub fn inject_fault_for(&mut self, id: impl Into<Id>, fault: Fault) {
+        lock(&MODEL).faults.push_back((Some(id.into()), fault));
+    }
+
+    /// Discards all injected faults that have not been applied yet.
+    pub fn clear_faults(&mut self) {
+        lock(&MODEL).faults.clear();
+    }
+}
+
+/// A disturbance of a frame on the virtual bus, see [`Sim::inject_fault`].
+///
+/// The error is detected by all peripherals taking part in the transmission. Those transmitting
+/// the frame increment their transmit error counter by 8 (except for acknowledgement errors in
+/// error passive state) and retry later, unless automatic retransmission is disabled. Those
+/// receiving it increment their receive error counter by 1 and discard the frame. Both record the
+/// error in the last error code, and raise the error interrupt flag if enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The frame violates the bit stuffing rule. All nodes detect a stuff error.
+    Stuff,
+    /// A bit in a fixed-form field, such as the CRC delimiter, is dominant. All nodes detect a
+    /// form error.
+    Form,
+    /// The frame is corrupted without the transmitters noticing it. Receivers detect a CRC error
+    /// and do not acknowledge the frame, so transmitters detect an acknowledgement error.
+    Crc,
+    /// The acknowledgement slot stays recessive. Transmitters detect an acknowledgement error,
+    /// and receivers detect their error flag as a form error.
+    Acknowledgement,
+    /// A dominant bit sent by the transmitters is read back as recessive. Transmitters detect a
+    /// dominant bit error, and receivers detect their error flag as a stuff error.
+    BitDominant,
+    /// A recessive bit sent by the transmitters is read back as dominant, outside of the
+    /// arbitration field. Transmitters detect a recessive bit error, and receivers detect their
+    /// error flag as a stuff error.
+    BitRecessive,
+    /// Inverts the bit at the given position on the wire, counting from 0 at the start of frame
+    /// bit and including stuff bits.
+    ///
+    /// The outcome depends on the field the bit belongs to:
+    ///
+    /// * Up to the CRC delimiter, transmitters detect a bit error, except for a recessive bit in
+    ///   the arbitration field, which makes them lose arbitration. Receivers detect a stuff error.
+    /// * Inverting the acknowledgement slot makes an acknowledged frame unacknowledged, and vice
+    ///   versa.
+    /// * In the CRC delimiter, the acknowledgement delimiter and the end of frame field, all nodes
+    ///   detect a form error. A dominant last bit of the end of frame field is ignored by
+    ///   receivers however, which accept the frame while transmitters retry it.
+    ///
+    /// Positions past the end of the frame have no effect.
+    FlipBit(u16),
+}
+
+/// The fields a frame consists of on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    StartOfFrame,
+    Arbitration,
+    Control,
+    Data,
+    Crc,
+    Stuff,
+    CrcDelimiter,
+    AckSlot,
+    AckDelimiter,
+    EndOfFrame,
+}
+
+/// Returns the bits of the frame in a transmit mailbox as they appear on the wire, with the
+/// fields they belong to. `true` is recessive.
+fn frame_bits(node: usize, idx: usize) -> Vec<(bool, Field)> {
+    fn push(bits: &mut Vec<(bool, Field)>, value: u32, len: u32, field: Field) {
+        for i in (0..len).rev() {
+            bits.push((value >> i & 1 != 0, field));
+        }
+    }
+
+    let mb = &regs(node).tx[idx];
+    let tir = get(&mb.tir);
+    let rtr = tir >> 1 & 1;
+    let dlc = get(&mb.tdtr) & TDTR_DLC;
+
+    let mut bits = Vec::new();
+    push(&mut bits, 0, 1, Field::StartOfFrame);
+    if tir & 0b100 == 0 {
+        push(&mut bits, tir >> 21, 11, Field::Arbitration);
+        push(&mut bits, rtr, 1, Field::Arbitration);
+        // IDE and r0.
+        push(&mut bits, 0, 2, Field::Control);
+    } else {
+        let id = tir >> 3;
+        push(&mut bits, id >> 18, 11, Field::Arbitration);
+        // SRR and IDE.
+        push(&mut bits, 0b11, 2, Field::Arbitration);
+        push(&mut bits, id & 0x3FFFF, 18, Field::Arbitration);
+        push(&mut bits, rtr, 1, Field::Arbitration);
+        // r1 and r0.
+        push(&mut bits, 0, 2, Field::Control);
+    }
+    push(&mut bits, dlc, 4, Field::Control);
+    if rtr == 0 {
+        let data = u64::from(get(&mb.tdhr)) << 32 | u64::from(get(&mb.tdlr));
+        for byte in data.to_le_bytes().iter().take(dlc.min(8) as usize) {
+            push(&mut bits, u32::from(*byte), 8, Field::Data);
+        }
+    }
+
+    let mut crc = 0u16;
+    for &(bit, _) in &bits {
+        let feedback = bit != (crc >> 14 & 1 != 0);
+        crc = crc << 1 & 0x7FFF;
+        if feedback {
+            crc ^= 0x4599;
+        }
+    }
+    push(&mut bits, u32::from(crc), 15, Field::Crc);
+
+    // After 5 consecutive bits of the same value, a bit of the opposite value is inserted.
+    let mut stuffed = Vec::with_capacity(bits.len() + bits.len() / 4 + 10);
+    let mut run = 0;
+    for (bit, field) in bits {
+        if run == 5 {
+            let last = stuffed.last().map(|&(bit, _)| bit) == Some(true);
+            stuffed.push((!last, Field::Stuff));
+            run = 1;
+        }
+        if stuffed.last().map(|&(bit, _)| bit) == Some(bit) {
+            run += 1;
+        } else {
+            run = 1;
+        }
+        stuffed.push((bit, field));
+    }
+    if run == 5 {
+        let last = stuffed.last().map(|&(bit, _)| bit) == Some(true);
+        stuffed.push((!last, Field::Stuff));
+    }
+
+    push(&mut stuffed, 1, 1, Field::CrcDelimiter);
+    push(&mut stuffed, 1, 1, Field::AckSlot);
+    push(&mut stuffed, 1, 1, Field::AckDelimiter);
+    push(&mut stuffed, 0x7F, 7, Field::EndOfFrame);
+    stuffed
+}
+
+/// What happens to a frame affected by a [`Fault`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Effect {
+    /// Transmitters and receivers detect errors with the given error codes.
+    Error { transmitter: u32, receiver: u32 },
+    /// Transmitters lose arbitration, and receivers detect a stuff error.
+    ArbitrationLost,
+    /// Inverts the acknowledgement slot.
+    InvertAck,
+    /// Receivers accept the frame, but transmitters detect a form error.
+    LastBitOfEndOfFrame,
+}
+
+impl Fault {
+    /// Returns the effect of the fault on the frame in a transmit mailbox.
+    fn effect(self, node: usize, idx: usize) -> Option<Effect> {
+        let (transmitter, receiver) = match self {
+            Fault::Stuff => (LEC_STUFF, LEC_STUFF),
+            Fault::Form => (LEC_FORM, LEC_FORM),
+            Fault::Crc => (LEC_ACKNOWLEDGEMENT, LEC_CRC),
+            Fault::Acknowledgement => (LEC_ACKNOWLEDGEMENT, LEC_FORM),
+            Fault::BitDominant => (LEC_BIT_DOMINANT, LEC_STUFF),
+            Fault::BitRecessive => (LEC_BIT_RECESSIVE, LEC_STUFF),
+            Fault::FlipBit(position) => {
+                let bits = frame_bits(node, idx);
+                let (recessive, field) = *bits.get(usize::from(position))?;
+                let bit_error = if recessive {
+                    LEC_BIT_RECESSIVE
+                } else {
+                    LEC_BIT_DOMINANT
+                };
+                return Some(match field {
+                    Field::Arbitration if recessive => Effect::ArbitrationLost,
+                    Field::StartOfFrame
+                    | Field::Arbitration
+                    | Field::Control
+                    | Field::Data
+                    | Field::Crc
+                    | Field::Stuff => Effect::Error {
+                        transmitter: bit_error,
+                        receiver: LEC_STUFF,
+                    },
+                    Field::AckSlot => Effect::InvertAck,
+                    Field::EndOfFrame if usize::from(position) == bits.len() - 1 => {
+                        Effect::LastBitOfEndOfFrame
+                    }
+                    Field::CrcDelimiter | Field::AckDelimiter | Field::EndOfFrame => {
+                        Effect::Error {
+                            transmitter: LEC_FORM,
+                            receiver: LEC_FORM,
+                        }
+                    }
+                });
+            }
+        };
+        Some(Effect::Error {
+            transmitter,
+            receiver,
+        })
+    }
+}
+
+/// Orders filters by their priority: 16-bit after 32-bit, mask after list, then by position.
+type FilterKey = (bool, bool, usize, usize);
+
+/// Contents of a receive FIFO entry, in register format.
+#[derive(Clone, Copy)]
+struct RxMailbox {
+    rir: u32,
+    rdtr: u32,
+    rdlr: u32,
+    rdhr: u32,
+}
+
+struct Node {
+    fifos: [VecDeque<RxMailbox>; 2],
+    /// For each transmit mailbox, the sequence number of its pending transmit request.
+    tx_requests: [Option<u64>; 3],
+    /// Transmit error counter, which exceeds 255 in bus-off state.
+    tec: u16,
+    /// Receive error counter, or the number of recovery sequences in bus-off state.
+    rec: u16,
+    bus_off: bool,
+    /// Software requested bus-off recovery by leaving initialization mode.
+    recovery_requested: bool,
+}
+
+impl Node {
+    const fn new() -> Self {
+        Self {
+            fifos: [VecDeque::new(), VecDeque::new()],
+            tx_requests: [None; 3],
+            tec: 0,
+            rec: 0,
+            bus_off: false,
+            recovery_requested: false,
+        }
+    }
+
+    fn is_error_passive(&self) -> bool {
+        self.tec > 127 || self.rec > 127
+    }
+}
+
+/// Role of a peripheral during a step on the virtual bus.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// Not connected to the bus in this step.
+    Offline,
+    /// Listens to the bus.
+    Receiver,
+    /// Transmits the frame in the given mailbox.
+    Transmitter(usize),
+}
+
+struct Model {
+    nodes: [Node; NODES],
+    time: u16,
+    sequence: u64,
+    /// Injected faults, with the identifier of the frames they apply to.
+    faults: VecDeque<(Option<Id>, Fault)>,
+}
+
+impl Model {
+    const fn new() -> Self {
+        Self {
+            nodes: [Node::new(), Node::new(), Node::new()],
+            time: 0,
+            sequence: 0,
+            faults: VecDeque::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.time = 0;
+        self.sequence = 0;
+        self.faults.clear();
+        for node in 0..NODES {
+            self.reset_node(node);
+            self.reset_filters(node);
+        }
+    }
+
+    /// Performs a software master reset of the peripheral, which leaves the filters alone.
+    fn reset_node(&mut self, node: usize) {
+        let r = regs(node);
+        set(&r.mcr, MCR_RESET_VALUE);
+        set(&r.msr, MSR_RESET_VALUE);
+        set(&r.tsr, TSR_RESET_VALUE);
+        for rfr in &r.rfr {
+            set(rfr, 0);
+        }
+        set(&r.ier, 0);
+        set(&r.esr, 0);
+        set(&r.btr, BTR_RESET_VALUE);
+        for mb in &r.tx {
+            set(&mb.tir, 0);
+            set(&mb.tdtr, 0);
+            set(&mb.tdlr, 0);
+            set(&mb.tdhr, 0);
+        }
+        for mb in &r.rx {
+            set(&mb.rir, 0);
+            set(&mb.rdtr, 0);
+            set(&mb.rdlr, 0);
+            set(&mb.rdhr, 0);
+        }
+        self.nodes[node] = Node::new();
+    }
+
+    fn reset_filters(&mut self, node: usize) {
+        let r = regs(node);
+        set(&r.fmr, FMR_RESET_VALUE);
+        set(&r.fm1r, 0);
+        set(&r.fs1r, 0);
+        set(&r.ffa1r, 0);
+        set(&r.fa1r, 0);
+        for bank in &r.fb {
+            set(&bank.fr1, 0);
+            set(&bank.fr2, 0);
+        }
+    }
+
+    fn register_written(&mut self, node: usize, reg: usize, old: u32) {
+        let r = regs(node);
+        let new = unsafe { (reg as *const u32).read_volatile() };
+
+        if reg == addr(&r.mcr) {
+            if new & MCR_RESET != 0 {
+                self.reset_node(node);
+            } else {
+                if old & MCR_INRQ != 0 && new & MCR_INRQ == 0 && self.nodes[node].bus_off {
+                    self.nodes[node].recovery_requested = true;
+                }
+                self.update_mode(node);
+            }
+        } else if reg == addr(&r.msr) {
+            set(&r.msr, old & !(new & MSR_W1C));
+        } else if reg == addr(&r.tsr) {
+            self.tsr_written(node, old, new);
+        } else if let Some(fifo) = (0..2).find(|&i| reg == addr(&r.rfr[i])) {
+            self.rfr_written(node, fifo, old, new);
+        } else if reg == addr(&r.esr) {
+            set(&r.esr, (old & !ESR_LEC) | (new & ESR_LEC));
+        } else if reg == addr(&r.btr) {
+            // Only writable in initialization mode.
+            if get(&r.msr) & MSR_INAK == 0 {
+                set(&r.btr, old);
+            }
+        } else if let Some(idx) = (0..3).find(|&i| reg == addr(&r.tx[i].tir)) {
+            if self.nodes[node].tx_requests[idx].is_some() {
+                // Write-protected while a transmission is pending.
+                set(&r.tx[idx].tir, old);
+            } else if new & TIR_TXRQ != 0 {
+                self.nodes[node].tx_requests[idx] = Some(self.sequence);
+                self.sequence += 1;
+                // A new request clears the status flags of the previous one.
+                set(&r.tsr, get(&r.tsr) & !(0xF << (8 * idx)));
+                self.sync_tsr(node);
+            }
+        } else if let Some(idx) = (0..3).find(|&i| {
+            reg == addr(&r.tx[i].tdtr) || reg == addr(&r.tx[i].tdlr) || reg == addr(&r.tx[i].tdhr)
+        }) {
+            if self.nodes[node].tx_requests[idx].is_some() {
+                set(unsafe { &*(reg as *const u32) }, old);
+            } else if reg == addr(&r.tx[idx].tdtr) {
+                // The timestamp is read-only.
+                set(&r.tx[idx].tdtr, (new & 0xFFFF) | (old & 0xFFFF_0000));
+            }
+        } else if (addr(&r.rx[0].rir)..addr(&r.fmr)).contains(&reg) {
+            // Receive mailboxes are read-only.
+            set(unsafe { &*(reg as *const u32) }, old);
+        } else if reg == addr(&r.fm1r) || reg == addr(&r.fs1r) || reg == addr(&r.ffa1r) {
+            if get(&r.fmr) & FMR_FINIT == 0 {
+                set(unsafe { &*(reg as *const u32) }, old);
+            }
+        } else if let Some(bank) =
+            (0..r.fb.len()).find(|&i| reg == addr(&r.fb[i].fr1) || reg == addr(&r.fb[i].fr2))
+        {
+            // Only writable when the bank is inactive or in filter initialization mode.
+            let active = get(&r.fa1r) & (1 << bank) != 0;
+            if active && get(&r.fmr) & FMR_FINIT == 0 {
+                set(unsafe { &*(reg as *const u32) }, old);
+            }
+        }
+    }
+
+    /// Acknowledges the mode requested in MCR.
+    fn update_mode(&mut self, node: usize) {
+        let r = regs(node);
+        let mcr = get(&r.mcr);
+        let old = get(&r.msr);
+        let mut msr = old & !(MSR_INAK | MSR_SLAK);
+        if mcr & MCR_INRQ != 0 {
+            msr |= MSR_INAK;
+        } else if mcr & MCR_SLEEP != 0 {
+            msr |= MSR_SLAK;
+            if old & MSR_SLAK == 0 {
+                msr |= MSR_SLAKI;
+            }
+        }
+        set(&r.msr, msr);
+    }
+
+    fn is_active(&self, node: usize) -> bool {
+        get(&regs(node).msr) & (MSR_INAK | MSR_SLAK) == 0
+    }
+
+    fn tsr_written(&mut self, node: usize, old: u32, new: u32) {
+        let r = regs(node);
+        let mut tsr = old;
+        for idx in 0..3 {
+            // Clearing RQCP also clears TXOK, ALST and TERR.
+            if new & rqcp(idx) != 0 {
+                tsr &= !(0xF << (8 * idx));
+            }
+        }
+        set(&r.tsr, tsr);
+
+        for idx in 0..3 {
+            if new & abrq(idx) != 0 && self.nodes[node].tx_requests[idx].is_some() {
+                self.complete_transmission(node, idx, false);
+            }
+        }
+        self.sync_tsr(node);
+    }
+
+    /// Compares two pending mailboxes. The greater one is transmitted first.
+    fn transmit_order(&self, node: usize, a: usize, b: usize) -> Ordering {
+        let r = regs(node);
+        if get(&r.mcr) & MCR_TXFP != 0 {
+            let requests = &self.nodes[node].tx_requests;
+            requests[b].cmp(&requests[a])
+        } else {
+            // Mailboxes with identical priority are transmitted in index order.
+            frame_id(node, a).cmp(&frame_id(node, b)).then(b.cmp(&a))
+        }
+    }
+
+    fn pending_mailboxes(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..3).filter(move |&idx| self.nodes[node].tx_requests[idx].is_some())
+    }
+
+    fn next_mailbox(&self, node: usize) -> Option<usize> {
+        self.pending_mailboxes(node)
+            .max_by(|&a, &b| self.transmit_order(node, a, b))
+    }
+
+    /// Updates the mailbox status bits in TSR.
+    fn sync_tsr(&mut self, node: usize) {
+        let r = regs(node);
+        let mut tsr = get(&r.tsr) & 0x00FF_FFFF;
+
+        let lowest = self
+            .pending_mailboxes(node)
+            .min_by(|&a, &b| self.transmit_order(node, a, b));
+        let free = (0..3).find(|&idx| self.nodes[node].tx_requests[idx].is_none());
+        for idx in 0..3 {
+            if self.nodes[node].tx_requests[idx].is_none() {
+                tsr |= 1 << (TSR_TME_SHIFT + idx as u32);
+            }
+        }
+        if let Some(lowest) = lowest {
+            if self.pending_mailboxes(node).count() > 1 {
+                tsr |= 1 << (TSR_LOW_SHIFT + lowest as u32);
+            }
+        }
+        // The next free mailbox, or the one with the lowest priority if all are full.
+        let code = free.or(lowest).unwrap_or(0);
+        tsr |= (code as u32) << TSR_CODE_SHIFT;
+
+        set(&r.tsr, tsr);
+    }
+
+    /// Empties a pending mailbox, after successful transmission or an abort.
+    fn complete_transmission(&mut self, node: usize, idx: usize, ok: bool) {
+        let r = regs(node);
+        self.nodes[node].tx_requests[idx] = None;
+        set(&r.tx[idx].tir, get(&r.tx[idx].tir) & !TIR_TXRQ);
+
+        let mut tsr = get(&r.tsr) | rqcp(idx);
+        if ok {
+            tsr |= txok(idx);
+        } else {
+            tsr &= !txok(idx);
+        }
+        set(&r.tsr, tsr);
+        self.sync_tsr(node);
+    }
+
+    fn rfr_written(&mut self, node: usize, fifo: usize, old: u32, new: u32) {
+        let r = regs(node);
+        set(&r.rfr[fifo], old & !(new & (RFR_FULL | RFR_FOVR)));
+        if new & RFR_RFOM != 0 {
+            self.nodes[node].fifos[fifo].pop_front();
+        }
+        self.sync_fifo(node, fifo);
+    }
+
+    /// Updates the FIFO's message count and output mailbox.
+    fn sync_fifo(&mut self, node: usize, fifo: usize) {
+        let r = regs(node);
+        let entries = &self.nodes[node].fifos[fifo];
+        set(
+            &r.rfr[fifo],
+            (get(&r.rfr[fifo]) & !RFR_FMP) | entries.len() as u32,
+        );
+        if let Some(head) = entries.front() {
+            let mb = &r.rx[fifo];
+            set(&mb.rir, head.rir);
+            set(&mb.rdtr, head.rdtr);
+            set(&mb.rdlr, head.rdlr);
+            set(&mb.rdhr, head.rdhr);
+        }
+    }
+
+    fn step(&mut self) -> bool {
+        let mut transmitted = false;
+        let mut roles = [Role::Offline; NODES];
+        for (node, role) in roles.iter_mut().enumerate() {
+            let r = regs(node);
+            let btr = get(&r.btr);
+            if get(&r.msr) & MSR_INAK != 0 || self.nodes[node].bus_off {
+                continue;
+            }
+
+            let next = self.next_mailbox(node).filter(|_| self.is_active(node));
+            if btr & (BTR_LBKM | BTR_SILM) == BTR_LBKM | BTR_SILM {
+                // Silent loopback mode: internal feedback only.
+                if let Some(idx) = next {
+                    let frame = self.start_transmission(node, idx);
+                    self.receive(node, frame);
+                    self.complete_transmission(node, idx, true);
+                    transmitted = true;
+                }
+            } else if let (Some(idx), 0) = (next, btr & BTR_SILM) {
+                *role = Role::Transmitter(idx);
+            } else {
+                *role = Role::Receiver;
+            }
+        }
+
+        transmitted |= self.arbitrate(&mut roles);
+        self.recover_from_bus_off();
+        self.time = self.time.wrapping_add(1);
+        transmitted
+    }
+
+    /// Transmits one frame on the virtual bus. Returns `true` if it was transmitted successfully.
+    fn arbitrate(&mut self, roles: &mut [Role; NODES]) -> bool {
+        let transmitters = |roles: &[Role; NODES]| {
+            let roles = *roles;
+            (0..NODES).filter_map(move |node| match roles[node] {
+                Role::Transmitter(idx) => Some((node, idx)),
+                _ => None,
+            })
+        };
+
+        // Arbitration field.
+        let winner = transmitters(roles)
+            .map(|(node, idx)| frame_id(node, idx))
+            .max();
+        let winner = match winner {
+            Some(winner) => winner,
+            None => return false,
+        };
+        for (node, idx) in transmitters(roles) {
+            if frame_id(node, idx) != winner {
+                self.arbitration_lost(node, idx);
+                roles[node] = Role::Receiver;
+            }
+        }
+
+        // Control and data field: the first recessive bit that is overwritten by a dominant one
+        // causes a bit error.
+        let dominant = transmitters(roles)
+            .map(|(node, idx)| frame_contents(node, idx))
+            .min();
+        let mut destroyed = false;
+        for (node, idx) in transmitters(roles) {
+            if Some(frame_contents(node, idx)) != dominant {
+                destroyed |= !self.nodes[node].is_error_passive();
+                self.transmit_error(node, idx, LEC_BIT_RECESSIVE);
+                roles[node] = Role::Offline;
+            }
+        }
+        if destroyed {
+            // The active error flag is detected as a stuff error by all other nodes.
+            self.frame_error(roles, LEC_STUFF, LEC_STUFF);
+            return false;
+        }
+
+        let (node, idx) = transmitters(roles).next().unwrap();
+        let injected = self
+            .faults
+            .iter()
+            .position(|&(id, _)| id.is_none() || id == Some(winner.to_id()));
+        let effect = injected.and_then(|i| {
+            let (_, fault) = self.faults.remove(i).unwrap();
+            fault.effect(node, idx)
+        });
+
+        let mut acknowledged = (0..NODES).any(|node| {
+            let btr = get(&regs(node).btr);
+            roles[node] == Role::Receiver
+                && self.is_active(node)
+                && btr & (BTR_LBKM | BTR_SILM) == 0
+        });
+        let mut late_error = false;
+        match effect {
+            Some(Effect::Error {
+                transmitter,
+                receiver,
+            }) => {
+                self.frame_error(roles, transmitter, receiver);
+                return false;
+            }
+            Some(Effect::ArbitrationLost) => {
+                for (node, idx) in transmitters(roles) {
+                    self.arbitration_lost(node, idx);
+                    roles[node] = Role::Receiver;
+                }
+                // Nobody sends the rest of the frame, which violates the bit stuffing rule.
+                self.frame_error(roles, LEC_STUFF, LEC_STUFF);
+                return false;
+            }
+            Some(Effect::InvertAck) => acknowledged = !acknowledged,
+            Some(Effect::LastBitOfEndOfFrame) => late_error = true,
+            None => {}
+        }
+
+        // Acknowledgement slot.
+        let mut valid = false;
+        for (node, idx) in transmitters(roles) {
+            if acknowledged || get(&regs(node).btr) & BTR_LBKM != 0 {
+                valid = true;
+            } else {
+                self.transmit_error(node, idx, LEC_ACKNOWLEDGEMENT);
+                roles[node] = Role::Offline;
+            }
+        }
+        if !valid {
+            // The error flag of the transmitters starts in the acknowledgement delimiter.
+            self.frame_error(roles, LEC_FORM, LEC_FORM);
+            return false;
+        }
+
+        let mut frame = None;
+        for (node, idx) in transmitters(roles) {
+            let sent = self.start_transmission(node, idx);
+            if get(&regs(node).btr) & BTR_LBKM != 0 {
+                self.receive(node, sent);
+            }
+            if late_error {
+                self.transmit_error(node, idx, LEC_FORM);
+            } else {
+                self.complete_transmission(node, idx, true);
+                self.nodes[node].tec = self.nodes[node].tec.saturating_sub(1);
+                self.sync_esr(node, Some(0));
+            }
+            frame = Some(sent);
+        }
+        let frame = frame.unwrap();
+        for (node, role) in roles.iter().enumerate() {
+            if *role == Role::Receiver && get(&regs(node).btr) & BTR_LBKM == 0 {
+                self.receive(node, frame);
+                let counters = &mut self.nodes[node];
+                counters.rec = match counters.rec {
+                    0 => 0,
+                    1..=127 => counters.rec - 1,
+                    _ => 127,
+                };
+                self.sync_esr(node, Some(0));
+            }
+        }
+        !late_error
+    }
+
+    /// Signals an error detected by all peripherals taking part in a transmission.
+    fn frame_error(&mut self, roles: &[Role; NODES], transmitter: u32, receiver: u32) {
+        for (node, role) in roles.iter().enumerate() {
+            match *role {
+                Role::Transmitter(idx) => self.transmit_error(node, idx, transmitter),
+                Role::Receiver => self.receive_error(node, receiver),
+                Role::Offline => {}
+            }
+        }
+    }
+
+    fn arbitration_lost(&mut self, node: usize, idx: usize) {
+        let r = regs(node);
+        set(&r.tsr, get(&r.tsr) | alst(idx));
+        if get(&r.mcr) & MCR_NART != 0 {
+            self.complete_transmission(node, idx, false);
+        }
+    }
+
+    fn transmit_error(&mut self, node: usize, idx: usize, lec: u32) {
+        let r = regs(node);
+        set(&r.tsr, get(&r.tsr) | terr(idx));
+        if get(&r.mcr) & MCR_NART != 0 {
+            self.complete_transmission(node, idx, false);
+        }
+
+        let counters = &mut self.nodes[node];
+        // An error passive transmitter does not count missing acknowledgements.
+        if lec != LEC_ACKNOWLEDGEMENT || !counters.is_error_passive() {
+            counters.tec += 8;
+        }
+        if counters.tec > 255 {
+            counters.bus_off = true;
+            counters.recovery_requested = false;
+            // REC counts the recovery sequences while in bus-off state.
+            counters.rec = 0;
+        }
+        self.sync_esr(node, Some(lec));
+    }
+
+    fn receive_error(&mut self, node: usize, lec: u32) {
+        if !self.is_active(node) {
+            return;
+        }
+        let counters = &mut self.nodes[node];
+        counters.rec = (counters.rec + 1).min(255);
+        self.sync_esr(node, Some(lec));
+    }
+
+    fn recover_from_bus_off(&mut self) {
+        for node in 0..NODES {
+            let automatic = get(&regs(node).mcr) & MCR_ABOM != 0;
+            let counters = &mut self.nodes[node];
+            if !counters.bus_off || !(automatic || counters.recovery_requested) {
+                continue;
+            }
+
+            counters.rec += RECOVERY_SEQUENCES_PER_STEP;
+            if counters.rec >= RECOVERY_SEQUENCES {
+                *counters = Node {
+                    fifos: core::mem::take(&mut counters.fifos),
+                    tx_requests: counters.tx_requests,
+                    ..Node::new()
+                };
+            }
+            self.sync_esr(node, None);
+        }
+    }
+
+    /// Updates the error counters and flags in ESR, and sets the last error code if `lec` is
+    /// given.
+    fn sync_esr(&mut self, node: usize, lec: Option<u32>) {
+        let r = regs(node);
+        let counters = &self.nodes[node];
+        let old = get(&r.esr);
+
+        let tec = counters.tec.min(255) as u32;
+        let rec = counters.rec.min(255) as u32;
+        let mut esr = rec << 24 | tec << 16 | lec.unwrap_or(old & ESR_LEC);
+        // In bus-off state, REC is not an error counter.
+        if counters.tec >= 96 || (!counters.bus_off && counters.rec >= 96) {
+            esr |= ESR_EWGF;
+        }
+        if counters.is_error_passive() {
+            esr |= ESR_EPVF;
+        }
+        if counters.bus_off {
+            esr |= ESR_BOFF;
+        }
+        set(&r.esr, esr);
+
+        // Raise the error interrupt flag for enabled error conditions.
+        let ier = get(&r.ier);
+        let raised = esr & !old & (ESR_EWGF | ESR_EPVF | ESR_BOFF);
+        let lec_updated = matches!(lec, Some(lec) if lec != 0) && ier & IER_LECIE != 0;
+        if raised & (ier >> IER_ERROR_SHIFT) != 0 || lec_updated {
+            set(&r.msr, get(&r.msr) | MSR_ERRI);
+        }
+    }
+
+    /// Returns the frame in a transmit mailbox, inserting timestamps if enabled.
+    fn start_transmission(&mut self, node: usize, idx: usize) -> RxMailbox {
+        let r = regs(node);
+        let mb = &r.tx[idx];
+        let mut tdtr = get(&mb.tdtr);
+        let mut tdhr = get(&mb.tdhr);
+        if get(&r.mcr) & MCR_TTCM != 0 {
+            tdtr = (tdtr & 0xFFFF) | u32::from(self.time) << 16;
+            set(&mb.tdtr, tdtr);
+            if tdtr & TDTR_TGT != 0 && tdtr & TDTR_DLC == 8 {
+                // TIME[15:8] goes into byte 6, and TIME[7:0] into byte 7.
+                tdhr = (tdhr & 0xFFFF) | u32::from(self.time.swap_bytes()) << 16;
+                set(&mb.tdhr, tdhr);
+            }
+        }
+        RxMailbox {
+            rir: get(&mb.tir) & !TIR_TXRQ,
+            rdtr: tdtr & TDTR_DLC,
+            rdlr: get(&mb.tdlr),
+            rdhr: tdhr,
+        }
+    }
+
+    /// Passes a frame seen on the bus through the filters of a peripheral.
+    fn receive(&mut self, node: usize, mut frame: RxMailbox) {
+        let r = regs(node);
+        let msr = get(&r.msr);
+        if msr & MSR_SLAK != 0 {
+            // The start of frame wakes up the peripheral, but the frame itself is lost.
+            set(&r.msr, msr | MSR_WKUI);
+            if get(&r.mcr) & MCR_AWUM != 0 {
+                set(&r.mcr, get(&r.mcr) & !MCR_SLEEP);
+                self.update_mode(node);
+            }
+            return;
+        }
+        if msr & MSR_INAK != 0 {
+            return;
+        }
+
+        let (fifo, fmi) = match self.filter(node, frame.rir) {
+            Some(accepted) => accepted,
+            None => return,
+        };
+        frame.rdtr |= u32::from(self.time) << 16 | u32::from(fmi) << 8;
+
+        let entries = &mut self.nodes[node].fifos[fifo];
+        if entries.len() == FIFO_DEPTH {
+            set(&r.rfr[fifo], get(&r.rfr[fifo]) | RFR_FOVR);
+            if get(&r.mcr) & MCR_RFLM == 0 {
+                // The last frame is overwritten.
+                *entries.back_mut().unwrap() = frame;
+            }
+        } else {
+            entries.push_back(frame);
+            if entries.len() == FIFO_DEPTH {
+                set(&r.rfr[fifo], get(&r.rfr[fifo]) | RFR_FULL);
+            }
+        }
+        self.sync_fifo(node, fifo);
+    }
+
+    /// Returns the FIFO and filter match index for a frame, or `None` if it is not accepted.
+    fn filter(&self, node: usize, rir: u32) -> Option<(usize, u8)> {
+        let (owner, banks) = match node {
+            0 | 1 => {
+                let split = ((get(&regs(0).fmr) >> 8) & 0x3F) as usize;
+                if node == 0 {
+                    (0, 0..split)
+                } else {
+                    (0, split..SimCan1::NUM_FILTER_BANKS as usize)
+                }
+            }
+            _ => (node, 0..SimCan3::NUM_FILTER_BANKS as usize),
+        };
+        let r = regs(owner);
+        if get(&r.fmr) & FMR_FINIT != 0 {
+            // Reception is deactivated while the filters are initialized.
+            return None;
+        }
+
+        let (fm1r, fs1r, ffa1r, fa1r) = (get(&r.fm1r), get(&r.fs1r), get(&r.ffa1r), get(&r.fa1r));
+        let id32 = rir & !1;
+        let id16 =
+            (rir >> 21) << 5 | ((rir >> 1) & 1) << 4 | ((rir >> 2) & 1) << 3 | (rir >> 18) & 7;
+
+        // Filter match indices are assigned per FIFO, counting inactive banks as well.
+        let mut next_fmi = [0u8; 2];
+        // Priority of the best match: 32-bit before 16-bit, list before mask, then by number.
+        let mut best: Option<(FilterKey, (usize, u8))> = None;
+        for bank in banks {
+            let list = fm1r & (1 << bank) != 0;
+            let wide = fs1r & (1 << bank) != 0;
+            let fifo = (ffa1r >> bank & 1) as usize;
+            let (fr1, fr2) = (get(&r.fb[bank].fr1), get(&r.fb[bank].fr2));
+
+            let matches: &[bool] = match (wide, list) {
+                (true, false) => &[(id32 ^ fr1) & fr2 & !1 == 0],
+                (true, true) => &[(id32 ^ fr1) & !1 == 0, (id32 ^ fr2) & !1 == 0],
+                (false, false) => &[
+                    (id16 ^ fr1) & (fr1 >> 16) & 0xFFFF == 0,
+                    (id16 ^ fr2) & (fr2 >> 16) & 0xFFFF == 0,
+                ],
+                (false, true) => &[
+                    id16 == fr1 & 0xFFFF,
+                    id16 == fr1 >> 16,
+                    id16 == fr2 & 0xFFFF,
+                    id16 == fr2 >> 16,
+                ],
+            };
+
+            if fa1r & (1 << bank) != 0 {
+                for (slot, _) in matches.iter().enumerate().filter(|(_, m)| **m) {
+                    let key = (!wide, !list, bank, slot);
+                    if !matches!(best, Some((best_key, _)) if best_key <= key) {
+                        best = Some((key, (fifo, next_fmi[fifo] + slot as u8)));
+                    }
+                }
+            }
+            next_fmi[fifo] += matches.len() as u8;
+        }
+
+        best.map(|(_, accepted)| accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Mask32;
+    use crate::{
+        BusOffRecovery, Can, Error, ExtendedId, Fifo, Frame, Interrupt, Interrupts, StandardId,
+    };
+
+    fn normal<I: Instance>(instance: I) -> Can<I> {
+        Can::builder(instance).enable()
+    }
+
+    fn loopback<I: Instance>(instance: I) -> Can<I> {
+        Can::builder(instance)
+            .set_loopback(true)
+            .set_silent(true)
+            .enable()
+    }
+
+    #[test]
+    fn mode_handshakes() {
+        let (_sim, p) = Sim::new();
+
+        let mut can = Can::builder(p.can1).leave_disabled();
+        assert!(can.enable_non_blocking().is_err());
+        assert!(can.enable_non_blocking().is_ok());
+
+        can.sleep();
+        can.wakeup();
+        can.modify_config().set_loopback(true).enable();
+        assert!(can.bit_timing().loopback());
+    }
+
+    #[test]
+    fn software_reset() {
+        let (mut sim, p) = Sim::new();
+        let mut can = loopback(p.can1);
+        can.transmit(&Frame::new_data(StandardId::ZERO, []))
+            .unwrap();
+
+        can.modify_config().set_loopback(false).enable();
+        // Without a bus, nothing is transmitted.
+        assert!(!sim.step());
+
+        // Safety: Only the simulated peripheral is reset.
+        unsafe { &*SimCan1::REGISTERS }
+            .mcr
+            .write(|w| w.reset().set_bit());
+        assert!(can.is_transmitter_idle());
+        assert_eq!(can.bit_timing().btr(), BTR_RESET_VALUE);
+    }
+
+    #[test]
+    fn bus_roundtrip() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = normal(p.can1);
+        let mut can3 = normal(p.can3);
+        can1.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+        can3.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo1, Mask32::accept_all());
+
+        let frame = Frame::new_data(ExtendedId::new(0x1234).unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        can1.transmit(&frame).unwrap();
+        assert!(sim.step());
+        assert!(can1.is_transmitter_idle());
+        assert!(can1.receive().is_err());
+        assert_eq!(can3.rx1().receive().unwrap(), frame);
+
+        let status = can1.error_status();
+        assert_eq!(status.transmit_counter(), 0);
+        assert_eq!(status.last_error(), Error::None);
+    }
+
+    #[test]
+    fn bus_arbitration() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = normal(p.can1);
+        let mut can3 = normal(p.can3);
+        can1.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+        can3.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        // CAN1 enqueues frames with increasing priority, and then a frame with even higher
+        // priority, which replaces the lowest-priority one.
+        let frames = [4, 3, 2, 0].map(|id| Frame::new_data(StandardId::new(id).unwrap(), []));
+        for frame in &frames[..3] {
+            assert!(can1.transmit(frame).unwrap().dequeued_frame().is_none());
+        }
+        let status = can1.transmit(&frames[3]).unwrap();
+        assert_eq!(status.dequeued_frame(), Some(&frames[0]));
+
+        // A standard frame takes priority over an extended frame with the same base ID.
+        let extended = Frame::new_data(ExtendedId::new(1 << 18).unwrap(), []);
+        let standard = Frame::new_data(StandardId::new(1).unwrap(), []);
+        can3.transmit(&extended).unwrap();
+        can3.transmit(&standard).unwrap();
+
+        let expected = [
+            (1, &frames[3]),
+            (3, &standard),
+            (3, &extended),
+            (1, &frames[2]),
+            (1, &frames[1]),
+        ];
+        for (sender, frame) in expected {
+            assert!(sim.step());
+            let received = if sender == 1 {
+                can3.receive().unwrap()
+            } else {
+                can1.receive().unwrap()
+            };
+            assert_eq!(&received, frame);
+        }
+        assert!(!sim.step());
+    }
+
+    #[test]
+    fn bus_acknowledgement_error() {
+        let (mut sim, p) = Sim::new();
+        let mut can3 = normal(p.can3);
+
+        let frame = Frame::new_data(StandardId::new(1).unwrap(), []);
+        can3.transmit(&frame).unwrap();
+
+        // Each missing acknowledgement counts until the transmitter is error passive.
+        for tec in (8..=128).step_by(8) {
+            assert!(!sim.step());
+            let status = can3.error_status();
+            assert_eq!(status.transmit_counter(), tec);
+            assert_eq!(status.last_error(), Error::Acknowledgement);
+            assert_eq!(status.error_warning(), tec >= 96);
+            assert_eq!(status.error_passive(), tec > 127);
+        }
+        for _ in 0..32 {
+            assert!(!sim.step());
+        }
+        let status = can3.error_status();
+        assert_eq!(status.transmit_counter(), 128);
+        assert!(!status.bus_off());
+        assert!(!can3.is_transmitter_idle());
+
+        // Another node acknowledges the frame, even without accepting it.
+        let _can1 = normal(p.can1);
+        assert!(sim.step());
+        assert!(can3.is_transmitter_idle());
+        let status = can3.error_status();
+        assert_eq!(status.transmit_counter(), 127);
+        assert!(!status.error_passive());
+        assert_eq!(status.last_error(), Error::None);
+    }
+
+    /// Two nodes sending different frames with the same identifier cause bit errors, until one
+    /// of them goes bus-off.
+    #[test]
+    fn bus_off_and_recovery() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = normal(p.can1);
+        let mut can2 = Can::builder(p.can2)
+            .set_bus_off_recovery(BusOffRecovery::Manual)
+            .enable();
+        let _can3 = normal(p.can3);
+
+        can1.enable_interrupt(Interrupt::BusOff);
+        can2.enable_interrupt(Interrupt::BusOff);
+
+        let id = StandardId::new(0x100).unwrap();
+        let dominant = Frame::new_data(id, [1]);
+        let recessive = Frame::new_data(id, [2]);
+        let mut steps = 0;
+        while !can2.error_status().bus_off() {
+            if can1.is_transmitter_idle() {
+                can1.transmit(&dominant).unwrap();
+            }
+            if can2.is_transmitter_idle() {
+                can2.transmit(&recessive).unwrap();
+            }
+            sim.step();
+            steps += 1;
+            assert!(steps < 100);
+        }
+
+        let status = can2.error_status();
+        assert_eq!(status.last_error(), Error::BitRecessive);
+        assert!(error_interrupt_pending(&can2));
+        let status = can1.error_status();
+        assert!(!status.bus_off());
+        assert!(status.transmit_counter() < 128);
+        assert!(!error_interrupt_pending(&can1));
+
+        // Without automatic recovery, the node stays in bus-off state until software requests
+        // recovery.
+        for _ in 0..32 {
+            sim.step();
+        }
+        assert_eq!(can2.bus_off_recovery_progress(), Some(0));
+
+        assert!(can2.recover_from_bus_off().is_err());
+        sim.step();
+        assert_eq!(can2.bus_off_recovery_progress(), Some(8));
+        while can2.recover_from_bus_off().is_err() {
+            sim.step();
+        }
+        let status = can2.error_status();
+        assert_eq!(status.transmit_counter(), 0);
+        assert_eq!(status.receive_counter(), 0);
+
+        // The pending frame is transmitted after recovery.
+        assert!(!can2.is_transmitter_idle());
+        sim.step();
+        assert!(can2.is_transmitter_idle());
+    }
+
+    fn errors(can1: &Can<SimCan1>, can3: &Can<SimCan3>) -> [(Error, u8); 2] {
+        let tx = can1.error_status();
+        let rx = can3.error_status();
+        [
+            (tx.last_error(), tx.transmit_counter()),
+            (rx.last_error(), rx.receive_counter()),
+        ]
+    }
+
+    fn error_interrupt_pending<I: Instance>(can: &Can<I>) -> bool {
+        can.registers().msr.read().erri().bit_is_set()
+    }
+
+    #[test]
+    fn injected_faults() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = normal(p.can1);
+        let mut can3 = normal(p.can3);
+        can3.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+        can1.enable_interrupt(Interrupt::LastErrorCode);
+
+        let faults = [
+            (Fault::Stuff, Error::Stuff, Error::Stuff),
+            (Fault::Form, Error::Form, Error::Form),
+            (Fault::Crc, Error::Acknowledgement, Error::Crc),
+            (Fault::Acknowledgement, Error::Acknowledgement, Error::Form),
+            (Fault::BitDominant, Error::BitDominant, Error::Stuff),
+            (Fault::BitRecessive, Error::BitRecessive, Error::Stuff),
+        ];
+        let frame = Frame::new_data(StandardId::new(0x10).unwrap(), [0xAA]);
+        for (fault, tx_error, rx_error) in faults {
+            sim.inject_fault(fault);
+            can1.transmit(&frame).unwrap();
+            assert!(!sim.step());
+            assert_eq!(errors(&can1, &can3), [(tx_error, 8), (rx_error, 1)]);
+            assert!(error_interrupt_pending(&can1));
+            assert!(!error_interrupt_pending(&can3));
+            can1.clear_error_interrupt();
+
+            // The frame is retransmitted.
+            assert!(can3.receive().is_err());
+            assert!(sim.step());
+            assert_eq!(can3.receive().unwrap(), frame);
+            assert_eq!(errors(&can1, &can3), [(Error::None, 7), (Error::None, 0)]);
+            assert!(!error_interrupt_pending(&can1));
+
+            // Let the transmit error counter drop back to 0.
+            for _ in 0..7 {
+                can1.transmit(&frame).unwrap();
+                assert!(sim.step());
+                can3.receive().unwrap();
+            }
+        }
+
+        // Faults apply to matching frames only.
+        let other = Frame::new_data(StandardId::new(0x11).unwrap(), []);
+        sim.inject_fault_for(StandardId::new(0x10).unwrap(), Fault::Stuff);
+        can1.transmit(&other).unwrap();
+        assert!(sim.step());
+        can1.transmit(&frame).unwrap();
+        assert!(!sim.step());
+        assert_eq!(can1.error_status().last_error(), Error::Stuff);
+
+        sim.inject_fault(Fault::Stuff);
+        sim.clear_faults();
+        assert!(sim.step());
+    }
+
+    #[test]
+    fn frame_bits_encoding() {
+        let (_sim, p) = Sim::new();
+        let mut can = normal(p.can1);
+
+        let frames = [
+            Frame::new_data(StandardId::new(0).unwrap(), [0; 8]),
+            Frame::new_data(ExtendedId::MAX, [0xFF; 8]),
+            Frame::new_remote(StandardId::new(0x555).unwrap(), 3),
+        ];
+        for frame in &frames {
+            let mailbox = can.transmit(frame).unwrap().mailbox();
+            let bits = frame_bits(0, mailbox as usize);
+            can.abort(mailbox);
+
+            // At most 5 consecutive bits of the same value in the stuffed part.
+            let stuffed = bits
+                .iter()
+                .take_while(|(_, field)| *field != Field::CrcDelimiter)
+                .map(|(bit, _)| *bit)
+                .collect::<Vec<_>>();
+            assert!(stuffed.windows(6).all(|w| w.iter().any(|bit| *bit != w[0])));
+
+            // The CRC leaves no remainder.
+            let mut crc = 0u16;
+            for (bit, field) in &bits {
+                match field {
+                    Field::Stuff => continue,
+                    Field::CrcDelimiter => break,
+                    _ => {}
+                }
+                let feedback = *bit != (crc >> 14 & 1 != 0);
+                crc = crc << 1 & 0x7FFF;
+                if feedback {
+                    crc ^= 0x4599;
+                }
+            }
+            assert_eq!(crc, 0);
+
+            let data_bits = bits.iter().filter(|(_, f)| *f == Field::Data).count();
+            assert_eq!(data_bits, frame.data().map_or(0, |data| data.len() * 8));
+            assert_eq!(bits[bits.len() - 10].1, Field::CrcDelimiter);
+        }
+    }
+
+    #[test]
+    fn flipped_bits() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = normal(p.can1);
+        let mut can3 = normal(p.can3);
+        can3.modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        // The identifier starts with a recessive bit, followed by a dominant one.
+        let frame = Frame::new_data(StandardId::new(0x400).unwrap(), [1, 2]);
+        let mailbox = can1.transmit(&frame).unwrap().mailbox();
+        let bits = frame_bits(0, mailbox as usize);
+        let ack_slot = bits.len() - 9;
+        assert!(bits[ack_slot].1 == Field::AckSlot);
+
+        sim.inject_fault(Fault::FlipBit(0));
+        assert!(!sim.step());
+        assert_eq!(
+            errors(&can1, &can3),
+            [(Error::BitDominant, 8), (Error::Stuff, 1)]
+        );
+
+        // Losing arbitration turns the transmitter into a receiver.
+        sim.inject_fault(Fault::FlipBit(1));
+        assert!(!sim.step());
+        assert_eq!(errors(&can1, &can3), [(Error::Stuff, 8), (Error::Stuff, 2)]);
+        assert!(can1.registers().tsr.read().alst0().bit_is_set());
+
+        sim.inject_fault(Fault::FlipBit(2));
+        assert!(!sim.step());
+        assert_eq!(
+            errors(&can1, &can3),
+            [(Error::BitDominant, 16), (Error::Stuff, 3)]
+        );
+
+        sim.inject_fault(Fault::FlipBit(ack_slot as u16));
+        assert!(!sim.step());
+        assert_eq!(
+            errors(&can1, &can3),
+            [(Error::Acknowledgement, 24), (Error::Form, 4)]
+        );
+
+        sim.inject_fault(Fault::FlipBit(ack_slot as u16 + 1));
+        assert!(!sim.step());
+        assert_eq!(errors(&can1, &can3), [(Error::Form, 32), (Error::Form, 5)]);
+
+        // A dominant last bit of the end of frame field makes the transmitter retry a frame that
+        // was already received.
+        sim.inject_fault(Fault::FlipBit(bits.len() as u16 - 1));
+        assert!(!sim.step());
+        assert_eq!(errors(&can1, &can3), [(Error::Form, 40), (Error::None, 4)]);
+        assert_eq!(can3.receive().unwrap(), frame);
+
+        sim.inject_fault(Fault::FlipBit(bits.len() as u16));
+        assert!(sim.step());
+        assert_eq!(can3.receive().unwrap(), frame);
+        assert_eq!(errors(&can1, &can3), [(Error::None, 39), (Error::None, 3)]);
+    }
+
+    #[test]
+    fn injected_bus_off() {
+        let (mut sim, p) = Sim::new();
+        let mut can1 = normal(p.can1);
+        let mut can3 = normal(p.can3);
+        can1.enable_interrupts(
+            Interrupts::ERROR_WARNING | Interrupts::ERROR_PASSIVE | Interrupts::BUS_OFF,
+        );
+        can3.enable_interrupt(Interrupt::ErrorPassive);
+
+        let frame = Frame::new_data(StandardId::new(0x10).unwrap(), []);
+        can1.transmit(&frame).unwrap();
+        for step in 1..=32 {
+            sim.inject_fault(Fault::BitRecessive);
+            assert!(!sim.step());
+
+            let status = can1.error_status();
+            assert_eq!(status.error_warning(), step >= 12);
+            assert_eq!(status.error_passive(), step >= 16);
+            assert_eq!(status.bus_off(), step == 32);
+            let raised = step == 12 || step == 16 || step == 32;
+            assert_eq!(error_interrupt_pending(&can1), raised, "step {}", step);
+            can1.clear_error_interrupt();
+
+            assert_eq!(can3.error_status().receive_counter(), step);
+            assert!(!error_interrupt_pending(&can3));
+        }
+        assert_eq!(can1.bus_off_recovery_progress(), Some(8));
+    }
+}
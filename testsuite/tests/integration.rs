@@ -4,7 +4,7 @@
 #[defmt_test::tests]
 mod tests {
     use bxcan::filter::{ListEntry32, Mask16, Mask32};
-    use bxcan::{ExtendedId, Fifo, Frame, Mailbox, StandardId};
+    use bxcan::{ExtendedId, Fifo, Frame, Mailbox, OverrunPolicy, StandardId, TransmitOrder};
 
     use nb::block;
     use testsuite::State;
@@ -104,6 +104,58 @@ mod tests {
         defmt::assert!(matches!(state.can1.receive(), Err(nb::Error::WouldBlock)));
     }
 
+    #[test]
+    fn receive_with_meta(state: &mut State) {
+        let id0 = StandardId::new(13).unwrap();
+        let id1 = StandardId::new(12).unwrap();
+        state.can1.modify_filters().clear().enable_bank(
+            0,
+            Fifo::Fifo1,
+            [
+                ListEntry32::data_frames_with_id(id0),
+                ListEntry32::data_frames_with_id(id1),
+            ],
+        );
+
+        let frame = Frame::new_data(id1, [1, 2, 3]);
+        block!(state.can1.transmit(&frame)).unwrap();
+        while !state.can1.is_transmitter_idle() {}
+
+        let envelope = state.can1.receive_with_meta().unwrap();
+        defmt::assert_eq!(*envelope.frame(), frame);
+        defmt::assert_eq!(envelope.fifo(), Fifo::Fifo1);
+        defmt::assert_eq!(envelope.filter_match_index(), 1);
+    }
+
+    #[test]
+    fn transmit_with_timestamp(state: &mut State) {
+        state
+            .can1
+            .modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+        state
+            .can1
+            .modify_config()
+            .set_time_triggered_mode(true)
+            .enable();
+
+        let frame = Frame::new_data(StandardId::new(1).unwrap(), [0; 8]);
+        let status = block!(state.can1.transmit_with_timestamp(&frame)).unwrap();
+        while !state.can1.is_transmitter_idle() {}
+        let timestamp = state.can1.transmit_timestamp(status.mailbox());
+
+        let received = state.can1.receive().unwrap();
+        let data = received.data().unwrap();
+        defmt::assert_eq!(u16::from_be_bytes([data[6], data[7]]), timestamp);
+
+        state
+            .can1
+            .modify_config()
+            .set_time_triggered_mode(false)
+            .enable();
+    }
+
     #[test]
     fn both_fifo_filters(state: &mut State) {
         let id = StandardId::new(42).unwrap();
@@ -144,6 +196,51 @@ mod tests {
         defmt::assert!(matches!(state.can1.receive(), Err(nb::Error::WouldBlock)));
     }
 
+    #[test]
+    fn rx_fifo_overrun_policy(state: &mut State) {
+        state
+            .can1
+            .modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+
+        let frames = [1, 2, 3, 4].map(|id| Frame::new_data(StandardId::new(id).unwrap(), []));
+
+        for (locked, policy, expected) in [
+            (false, OverrunPolicy::Overwrite, [0, 1, 3]),
+            (true, OverrunPolicy::Discard, [0, 1, 2]),
+        ] {
+            state
+                .can1
+                .modify_config()
+                .set_rx_fifo_locked(locked)
+                .enable();
+
+            for frame in &frames {
+                defmt::unwrap!(block!(state.can1.transmit(frame)));
+                while !state.can1.is_transmitter_idle() {}
+            }
+
+            let err = match state.can1.rx0().receive() {
+                Err(nb::Error::Other(err)) => err,
+                _ => defmt::panic!("expected overrun"),
+            };
+            defmt::assert_eq!(err.fifo(), Fifo::Fifo0);
+            defmt::assert_eq!(err.policy(), policy);
+
+            for i in expected {
+                defmt::assert_eq!(state.can1.rx0().receive().unwrap(), frames[i]);
+            }
+            defmt::assert!(matches!(state.can1.receive(), Err(nb::Error::WouldBlock)));
+        }
+
+        state
+            .can1
+            .modify_config()
+            .set_rx_fifo_locked(false)
+            .enable();
+    }
+
     #[test]
     fn no_filters_no_frames(state: &mut State) {
         state.can1.modify_filters().clear();
@@ -408,6 +505,60 @@ mod tests {
         state.go_fast();
     }
 
+    /// Tests that frames are sent in the order they were enqueued when FIFO transmit order is
+    /// configured, and that pending frames are never dequeued.
+    #[test]
+    fn fifo_transmit_order(state: &mut State) {
+        state
+            .can1
+            .modify_filters()
+            .clear()
+            .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+        state
+            .can1
+            .modify_config()
+            .set_transmit_order(TransmitOrder::Fifo)
+            .enable();
+
+        state.go_slow();
+
+        // Enqueue several frames with increasing priorities.
+        let frames = [4, 3, 2, 1].map(|id| Frame::new_data(ExtendedId::new(id).unwrap(), []));
+        for frame in &frames {
+            let tx_status = state.can1.transmit(frame).unwrap();
+            defmt::assert!(tx_status.dequeued_frame().is_none());
+        }
+
+        // All mailboxes are full and nothing may be dequeued, even for a higher-priority frame.
+        let frame0 = Frame::new_data(ExtendedId::new(0).unwrap(), []);
+        defmt::assert!(matches!(
+            state.can1.transmit(&frame0),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        for frame in &frames {
+            defmt::assert_eq!(block!(state.can1.receive()).unwrap(), *frame);
+        }
+
+        defmt::assert!(state.can1.is_transmitter_idle());
+
+        state
+            .can1
+            .modify_config()
+            .set_transmit_order(TransmitOrder::Priority)
+            .enable();
+        state.go_fast();
+    }
+
+    #[test]
+    fn debug_freeze(state: &mut State) {
+        state.can1.modify_config().set_debug_freeze(false).enable();
+        defmt::assert!(!state.can1.debug_freeze());
+
+        state.can1.modify_config().set_debug_freeze(true).enable();
+        defmt::assert!(state.can1.debug_freeze());
+    }
+
     #[test]
     fn enable_non_blocking(state: &mut State) {
         drop(state.can1.modify_config());